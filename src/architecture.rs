@@ -0,0 +1,412 @@
+use crate::convolution::{
+    ConvolutionLayer, ConvolutionSettings, Pooling, PoolingLayer, PoolingSettings, Shape,
+};
+use crate::network::{Activation, DenseLayer, FlattenLayer, Layer, Normalization};
+use rand::Rng;
+use std::fmt;
+
+//モデルの構造の記述。文字列の書式は例えば
+//conv(16,3)-maxpool(2)-dense(128,relu)-dropout(0.2)-dense(10)
+//要素は次の通りで、ハイフンで区切って浅い層から順に並べる。最後は出力層のdense(10)にする
+//dense(大きさ[,活性化関数])
+//conv(フィルタ数,カーネルの大きさ[,ストライド,パディング][,活性化関数])
+//maxpool(大きさ[,ストライド]) avgpool(大きさ[,ストライド]) flatten
+//dropout(率) batchnorm layernorm は直前の全結合層に付ける
+pub struct Architecture {
+    pub input_shape: Shape,
+    pub layers: Vec<LayerSpec>,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum LayerSpec {
+    Dense {
+        size: usize,
+        activation: Activation,
+        normalization: Normalization,
+        dropout_rate: f64,
+    },
+    Convolution {
+        num_filters: usize,
+        settings: ConvolutionSettings,
+        activation: Activation,
+    },
+    Pooling(PoolingSettings),
+    Flatten,
+}
+
+impl LayerSpec {
+    //この層の出力の形。入力に対して設定が不正ならNone
+    pub fn output_shape(&self, input_shape: Shape) -> Option<Shape> {
+        match self {
+            LayerSpec::Dense { size, .. } => Some(Shape::new(*size, 1, 1)),
+            LayerSpec::Convolution {
+                num_filters,
+                settings,
+                ..
+            } => settings.output_shape(input_shape, *num_filters),
+            LayerSpec::Pooling(settings) => settings.output_shape(input_shape),
+            LayerSpec::Flatten => Some(Shape::new(input_shape.size(), 1, 1)),
+        }
+    }
+
+    pub fn num_parameters(&self, input_shape: Shape) -> usize {
+        match self {
+            LayerSpec::Dense {
+                size,
+                normalization,
+                ..
+            } => {
+                let normalization_parameters = if *normalization == Normalization::None {
+                    0
+                } else {
+                    2 * size
+                };
+                size * (input_shape.size() + 1) + normalization_parameters
+            }
+            LayerSpec::Convolution {
+                num_filters,
+                settings,
+                ..
+            } => {
+                num_filters
+                    * (input_shape.channels * settings.kernel_size * settings.kernel_size + 1)
+            }
+            LayerSpec::Pooling(_) | LayerSpec::Flatten => 0,
+        }
+    }
+}
+
+impl Architecture {
+    pub fn parse(spec: &str, input_shape: Shape) -> Result<Architecture, String> {
+        let mut layers: Vec<LayerSpec> = Vec::new();
+        for element in spec.split('-').map(str::trim) {
+            let (name, arguments) = match element.split_once('(') {
+                Some((name, rest)) => {
+                    let arguments = rest
+                        .strip_suffix(')')
+                        .ok_or_else(|| format!("{}の括弧が閉じていません。", element))?;
+                    (
+                        name.trim(),
+                        arguments.split(',').map(str::trim).collect::<Vec<&str>>(),
+                    )
+                }
+                None => (element, Vec::new()),
+            };
+            let number = |i: usize| -> Result<usize, String> {
+                arguments
+                    .get(i)
+                    .and_then(|s| s.parse::<usize>().ok())
+                    .filter(|&x| x > 0)
+                    .ok_or_else(|| format!("{}の{}番目の値が不正です。", element, i + 1))
+            };
+            //最後の引数が数値でなければ活性化関数とみなす
+            let activation = |num_numbers: usize| -> Result<(Activation, usize), String> {
+                match arguments.last() {
+                    Some(last) if arguments.len() > num_numbers && last.parse::<f64>().is_err() => {
+                        Activation::from_name(last)
+                            .map(|activation| (activation, arguments.len() - 1))
+                            .ok_or_else(|| format!("{}は知らない活性化関数です。", last))
+                    }
+                    _ => Ok((Activation::LeakyRelu, arguments.len())),
+                }
+            };
+            match name {
+                "dense" => {
+                    let (activation, num_numbers) = activation(1)?;
+                    if num_numbers != 1 {
+                        return Err(format!("{}の引数の数が不正です。", element));
+                    }
+                    layers.push(LayerSpec::Dense {
+                        size: number(0)?,
+                        activation,
+                        normalization: Normalization::None,
+                        dropout_rate: 0.0,
+                    });
+                }
+                "conv" => {
+                    let (activation, num_numbers) = activation(2)?;
+                    let (stride, padding) = match num_numbers {
+                        2 => (1, 0),
+                        4 => (
+                            number(2)?,
+                            arguments[3]
+                                .parse::<usize>()
+                                .map_err(|_| format!("{}の4番目の値が不正です。", element))?,
+                        ),
+                        _ => return Err(format!("{}の引数の数が不正です。", element)),
+                    };
+                    layers.push(LayerSpec::Convolution {
+                        num_filters: number(0)?,
+                        settings: ConvolutionSettings {
+                            kernel_size: number(1)?,
+                            stride,
+                            padding,
+                        },
+                        activation,
+                    });
+                }
+                "maxpool" | "pool" | "avgpool" => {
+                    let size = number(0)?;
+                    let stride = match arguments.len() {
+                        1 => size,
+                        2 => number(1)?,
+                        _ => return Err(format!("{}の引数の数が不正です。", element)),
+                    };
+                    layers.push(LayerSpec::Pooling(PoolingSettings {
+                        kind: if name == "avgpool" {
+                            Pooling::Average
+                        } else {
+                            Pooling::Max
+                        },
+                        size,
+                        stride,
+                    }));
+                }
+                "flatten" => layers.push(LayerSpec::Flatten),
+                "dropout" => {
+                    let rate = arguments
+                        .first()
+                        .and_then(|s| s.parse::<f64>().ok())
+                        .filter(|x| (0.0..1.0).contains(x))
+                        .ok_or_else(|| format!("{}の率は0以上1未満にして下さい。", element))?;
+                    *last_dense(&mut layers, name)?.1 = rate;
+                }
+                "batchnorm" => *last_dense(&mut layers, name)?.0 = Normalization::Batch,
+                "layernorm" => *last_dense(&mut layers, name)?.0 = Normalization::Layer,
+                _ => return Err(format!("{}は知らない層です。", element)),
+            }
+        }
+
+        //出力層は活性化関数を使わずSoftmaxを掛ける
+        match layers.last_mut() {
+            Some(LayerSpec::Dense {
+                size: 10,
+                activation,
+                normalization: Normalization::None,
+                dropout_rate,
+            }) if *dropout_rate == 0.0 => *activation = Activation::Identity,
+            _ => {
+                return Err(
+                    "最後は正規化やドロップアウトの無い出力層dense(10)にして下さい。".to_string(),
+                )
+            }
+        }
+        let architecture = Architecture {
+            input_shape,
+            layers,
+        };
+        architecture.output_shapes()?;
+        Ok(architecture)
+    }
+
+    //各層の出力の形
+    pub fn output_shapes(&self) -> Result<Vec<Shape>, String> {
+        let mut shape = self.input_shape;
+        let mut shapes = Vec::with_capacity(self.layers.len());
+        for (i, layer) in self.layers.iter().enumerate() {
+            shape = layer.output_shape(shape).ok_or_else(|| {
+                format!(
+                    "{}番目の層 {} は入力の形 {}x{}x{} に対して大き過ぎます。",
+                    i, layer, shape.channels, shape.height, shape.width
+                )
+            })?;
+            shapes.push(shape);
+        }
+        Ok(shapes)
+    }
+
+    //各層の入力の形
+    pub fn input_shapes(&self) -> Vec<Shape> {
+        let mut shapes = vec![self.input_shape];
+        shapes.extend(self.output_shapes().unwrap());
+        shapes.pop();
+        shapes
+    }
+
+    pub fn num_parameters(&self) -> usize {
+        self.layers
+            .iter()
+            .zip(self.input_shapes())
+            .map(|(layer, shape)| layer.num_parameters(shape))
+            .sum()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{:>3}  {:<32}{:<14}{:>12}\n",
+            "#", "層", "出力の形", "パラメータ数"
+        );
+        for (i, ((layer, input_shape), output_shape)) in self
+            .layers
+            .iter()
+            .zip(self.input_shapes())
+            .zip(self.output_shapes().unwrap())
+            .enumerate()
+        {
+            summary += &format!(
+                "{:>3}  {:<32}{:<14}{:>12}\n",
+                i,
+                layer.to_string(),
+                format!(
+                    "{}x{}x{}",
+                    output_shape.channels, output_shape.height, output_shape.width
+                ),
+                layer.num_parameters(input_shape)
+            );
+        }
+        summary += &format!("合計パラメータ数: {}", self.num_parameters());
+        summary
+    }
+
+    //訓練用のネットワークを作る
+    pub fn build(&self) -> Vec<Layer> {
+        self.build_with_rng(&mut rand::thread_rng())
+    }
+
+    //重みの初期値をrngから作る。同じシードなら同じネットワークになる
+    pub fn build_with_rng(&self, rng: &mut impl Rng) -> Vec<Layer> {
+        let last_i = self.layers.len() - 1;
+        self.layers
+            .iter()
+            .zip(self.input_shapes())
+            .enumerate()
+            .map(|(i, (spec, input_shape))| match spec {
+                LayerSpec::Dense {
+                    size,
+                    activation,
+                    normalization,
+                    dropout_rate,
+                } => {
+                    let mut layer = DenseLayer::new(*size, input_shape.size(), i == last_i, rng);
+                    layer.activation = *activation;
+                    layer.normalization = *normalization;
+                    layer.dropout_rate = *dropout_rate;
+                    Layer::Dense(layer)
+                }
+                LayerSpec::Convolution {
+                    num_filters,
+                    settings,
+                    activation,
+                } => {
+                    let mut layer =
+                        ConvolutionLayer::new(*num_filters, *settings, input_shape, rng).unwrap();
+                    layer.activation = *activation;
+                    Layer::Convolution(layer)
+                }
+                LayerSpec::Pooling(settings) => {
+                    Layer::Pooling(PoolingLayer::new(*settings, input_shape).unwrap())
+                }
+                LayerSpec::Flatten => Layer::Flatten(FlattenLayer::new(input_shape)),
+            })
+            .collect()
+    }
+
+    //訓練用のネットワークから構造を取り出す
+    pub fn from_layers(input_shape: Shape, layers: &[Layer]) -> Architecture {
+        Architecture {
+            input_shape,
+            layers: layers
+                .iter()
+                .map(|layer| match layer {
+                    Layer::Dense(layer) => LayerSpec::Dense {
+                        size: layer.neurons.len(),
+                        activation: layer.activation,
+                        normalization: layer.normalization,
+                        dropout_rate: layer.dropout_rate,
+                    },
+                    Layer::Convolution(layer) => LayerSpec::Convolution {
+                        num_filters: layer.filters.len(),
+                        settings: layer.settings,
+                        activation: layer.activation,
+                    },
+                    Layer::Pooling(layer) => LayerSpec::Pooling(layer.settings),
+                    Layer::Flatten(_) => LayerSpec::Flatten,
+                })
+                .collect(),
+        }
+    }
+}
+
+//dropoutやbatchnormを付ける直前の全結合層
+fn last_dense<'a>(
+    layers: &'a mut [LayerSpec],
+    name: &str,
+) -> Result<(&'a mut Normalization, &'a mut f64), String> {
+    match layers.last_mut() {
+        Some(LayerSpec::Dense {
+            normalization,
+            dropout_rate,
+            ..
+        }) => Ok((normalization, dropout_rate)),
+        _ => Err(format!("{}は全結合層の直後にしか置けません。", name)),
+    }
+}
+
+impl fmt::Display for LayerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayerSpec::Dense {
+                size,
+                activation,
+                normalization,
+                dropout_rate,
+            } => {
+                match activation {
+                    Activation::LeakyRelu => write!(f, "dense({})", size)?,
+                    _ => write!(f, "dense({},{})", size, activation.name())?,
+                }
+                match normalization {
+                    Normalization::None => {}
+                    Normalization::Batch => write!(f, "-batchnorm")?,
+                    Normalization::Layer => write!(f, "-layernorm")?,
+                }
+                if *dropout_rate > 0.0 {
+                    write!(f, "-dropout({})", dropout_rate)?;
+                }
+                Ok(())
+            }
+            LayerSpec::Convolution {
+                num_filters,
+                settings,
+                activation,
+            } => {
+                write!(f, "conv({},{}", num_filters, settings.kernel_size)?;
+                if settings.stride != 1 || settings.padding != 0 {
+                    write!(f, ",{},{}", settings.stride, settings.padding)?;
+                }
+                if *activation != Activation::LeakyRelu {
+                    write!(f, ",{}", activation.name())?;
+                }
+                write!(f, ")")
+            }
+            LayerSpec::Pooling(settings) => {
+                let name = match settings.kind {
+                    Pooling::Max => "maxpool",
+                    Pooling::Average => "avgpool",
+                };
+                if settings.stride == settings.size {
+                    write!(f, "{}({})", name, settings.size)
+                } else {
+                    write!(f, "{}({},{})", name, settings.size, settings.stride)
+                }
+            }
+            LayerSpec::Flatten => write!(f, "flatten"),
+        }
+    }
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut layers: Vec<String> = self.layers.iter().map(LayerSpec::to_string).collect();
+        //出力層は読み込む時にlinearにするので活性化関数を書かない。途中のlinearの層は書かないとleaky_reluに戻る
+        if let Some(LayerSpec::Dense {
+            size,
+            activation: Activation::Identity,
+            ..
+        }) = self.layers.last()
+        {
+            *layers.last_mut().unwrap() = format!("dense({})", size);
+        }
+        write!(f, "{}", layers.join("-"))
+    }
+}
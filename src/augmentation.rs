@@ -0,0 +1,269 @@
+use crate::image::GrayImage;
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use std::f64::consts::PI;
+use std::fmt;
+
+const IMAGE_SIZE: usize = 28;
+//弾性変形の変位場を滑らかにするガウス関数の標準偏差と、強さ1の時の変位の大きさ(Simard et al. 2003)
+const ELASTIC_SIGMA: f64 = 4.0;
+const ELASTIC_ALPHA: f64 = 34.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TransformKind {
+    //回転・拡大縮小・せん断・平行移動
+    Affine,
+    //滑らかな乱数の変位場で画像を歪める
+    Elastic,
+    //ガウス雑音を足す
+    Noise,
+    //無作為な長方形を背景で塗り潰す
+    Erasing,
+    //線を太くするか細くする
+    Thickness,
+    //コントラストと明るさを揺らす
+    Contrast,
+}
+
+impl TransformKind {
+    pub fn from_name(name: &str) -> Option<TransformKind> {
+        match name {
+            "affine" => Some(TransformKind::Affine),
+            "elastic" => Some(TransformKind::Elastic),
+            "noise" => Some(TransformKind::Noise),
+            "erasing" => Some(TransformKind::Erasing),
+            "thickness" => Some(TransformKind::Thickness),
+            "contrast" => Some(TransformKind::Contrast),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TransformKind::Affine => "affine",
+            TransformKind::Elastic => "elastic",
+            TransformKind::Noise => "noise",
+            TransformKind::Erasing => "erasing",
+            TransformKind::Thickness => "thickness",
+            TransformKind::Contrast => "contrast",
+        }
+    }
+}
+
+//probabilityの確率でmagnitudeの強さの変換を掛ける
+#[derive(Clone)]
+pub struct Transform {
+    pub kind: TransformKind,
+    pub probability: f64,
+    pub magnitude: f64,
+}
+
+//訓練中にミニバッチを作る度に画像に掛けるデータ拡張。文字列の書式は例えば
+//affine(0.5,1)-elastic(0.3,1)-noise(0.5,0.1)
+//各要素は 種類(確率,強さ) で、書いた順に掛ける
+#[derive(Clone, Default)]
+pub struct Augmentation {
+    pub transforms: Vec<Transform>,
+    //乱数の種。指定するとミニバッチの選び方とデータ拡張が毎回同じになる
+    pub seed: Option<u64>,
+}
+
+impl Augmentation {
+    pub fn parse(spec: &str) -> Result<Augmentation, String> {
+        let mut transforms = Vec::new();
+        for element in spec.split('-').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, arguments) = element
+                .split_once('(')
+                .and_then(|(name, rest)| Some((name.trim(), rest.strip_suffix(')')?)))
+                .ok_or_else(|| format!("{}は 種類(確率,強さ) の形で書いて下さい。", element))?;
+            let kind = TransformKind::from_name(name)
+                .ok_or_else(|| format!("{}は知らないデータ拡張です。", name))?;
+            let values: Vec<f64> = arguments
+                .split(',')
+                .map(|s| s.trim().parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("{}の値が不正です。", element))?;
+            let [probability, magnitude] = values[..] else {
+                return Err(format!("{}の引数の数が不正です。", element));
+            };
+            if !(0.0..=1.0).contains(&probability) || magnitude < 0.0 {
+                return Err(format!(
+                    "{}の確率は0以上1以下、強さは0以上にして下さい。",
+                    element
+                ));
+            }
+            transforms.push(Transform {
+                kind,
+                probability,
+                magnitude,
+            });
+        }
+        Ok(Augmentation {
+            transforms,
+            seed: None,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    //28x28の画像に掛ける
+    pub fn apply(&self, pixels: &[f64], rng: &mut impl Rng) -> Vec<f64> {
+        let mut image = GrayImage::from_pixels(IMAGE_SIZE, IMAGE_SIZE, pixels.to_vec());
+        for transform in &self.transforms {
+            if rng.gen::<f64>() < transform.probability {
+                image = apply_transform(&image, transform.kind, transform.magnitude, rng);
+            }
+        }
+        image.pixels
+    }
+}
+
+impl fmt::Display for Augmentation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let transforms: Vec<String> = self
+            .transforms
+            .iter()
+            .map(|t| format!("{}({},{})", t.kind.name(), t.probability, t.magnitude))
+            .collect();
+        write!(f, "{}", transforms.join("-"))
+    }
+}
+
+fn apply_transform(
+    image: &GrayImage,
+    kind: TransformKind,
+    magnitude: f64,
+    rng: &mut impl Rng,
+) -> GrayImage {
+    let mut uniform = |range: f64| rng.gen_range(-1.0..=1.0) * range;
+    match kind {
+        //強さ1で回転±15度、拡大縮小±10%、せん断±0.2、平行移動±2画素
+        TransformKind::Affine => {
+            let angle = uniform(15.0 * magnitude) * PI / 180.0;
+            let scale = 1.0 + uniform(0.1 * magnitude);
+            let shear = uniform(0.2 * magnitude);
+            let (dx, dy) = (uniform(2.0 * magnitude), uniform(2.0 * magnitude));
+            let (sin, cos) = angle.sin_cos();
+            //出力の画素から元の画像の位置を逆に求める(中心を軸にする)
+            let center = (image.width as f64 - 1.0) / 2.0;
+            remap(image, |x, y| {
+                let (x, y) = (x - center - dx, y - center - dy);
+                let (x, y) = ((cos * x + sin * y) / scale, (-sin * x + cos * y) / scale);
+                (x - shear * y + center, y + center)
+            })
+        }
+        TransformKind::Elastic => {
+            let alpha = ELASTIC_ALPHA * magnitude;
+            let mut field = || {
+                let noise: Vec<f64> = (0..image.pixels.len()).map(|_| uniform(1.0)).collect();
+                gaussian_blur(
+                    &GrayImage::from_pixels(image.width, image.height, noise),
+                    ELASTIC_SIGMA,
+                )
+            };
+            let (field_x, field_y) = (field(), field());
+            remap(image, |x, y| {
+                let (ix, iy) = (x as usize, y as usize);
+                (
+                    x + alpha * field_x.get(ix, iy),
+                    y + alpha * field_y.get(ix, iy),
+                )
+            })
+        }
+        TransformKind::Noise => {
+            let normal = Normal::new(0.0, magnitude.max(f64::MIN_POSITIVE)).unwrap();
+            let mut noisy = image.clone();
+            for value in &mut noisy.pixels {
+                *value = (*value + normal.sample(rng)).clamp(0.0, 1.0);
+            }
+            noisy
+        }
+        //強さは消す面積の画像に対する割合の上限。縦横比は0.3~3.3
+        TransformKind::Erasing => {
+            let area = rng.gen_range(0.0..=magnitude.min(1.0)) * image.pixels.len() as f64;
+            let aspect = (rng.gen_range(0.3f64.ln()..=(1.0 / 0.3f64).ln())).exp();
+            let width = ((area * aspect).sqrt().round() as usize).min(image.width);
+            let height = ((area / aspect).sqrt().round() as usize).min(image.height);
+            let left = rng.gen_range(0..=image.width - width);
+            let top = rng.gen_range(0..=image.height - height);
+            let mut erased = image.clone();
+            for y in top..top + height {
+                for x in left..left + width {
+                    erased.set(x, y, 0.0);
+                }
+            }
+            erased
+        }
+        //強さ1で3x3の膨張か収縮をそのまま掛ける。それより弱ければ元の画像と混ぜる
+        TransformKind::Thickness => {
+            let target = if rng.gen::<bool>() {
+                image.dilate()
+            } else {
+                image.erode()
+            };
+            let rate = rng.gen_range(0.0..=magnitude.min(1.0));
+            let mut changed = image.clone();
+            for (value, target) in changed.pixels.iter_mut().zip(&target.pixels) {
+                *value += rate * (target - *value);
+            }
+            changed
+        }
+        //強さ1でコントラスト0.5~1.5倍、明るさ±0.1
+        TransformKind::Contrast => {
+            let contrast = 1.0 + uniform(0.5 * magnitude);
+            let brightness = uniform(0.1 * magnitude);
+            let mut changed = image.clone();
+            for value in &mut changed.pixels {
+                *value = ((*value - 0.5) * contrast + 0.5 + brightness).clamp(0.0, 1.0);
+            }
+            changed
+        }
+    }
+}
+
+//出力の各画素について元の画像の位置を求め、補間して埋める
+fn remap(image: &GrayImage, source: impl Fn(f64, f64) -> (f64, f64)) -> GrayImage {
+    let mut remapped = GrayImage::new(image.width, image.height);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (sx, sy) = source(x as f64, y as f64);
+            remapped.set(x, y, image.bilinear(sx, sy));
+        }
+    }
+    remapped
+}
+
+//縦横に分けて畳み込む。画像の外は0とする
+fn gaussian_blur(image: &GrayImage, sigma: f64) -> GrayImage {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = kernel.iter().sum();
+    let blur = |image: &GrayImage, horizontal: bool| {
+        let mut blurred = GrayImage::new(image.width, image.height);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let mut sum = 0f64;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as isize - radius;
+                    let (nx, ny) = if horizontal {
+                        (x as isize + offset, y as isize)
+                    } else {
+                        (x as isize, y as isize + offset)
+                    };
+                    if (0..image.width as isize).contains(&nx)
+                        && (0..image.height as isize).contains(&ny)
+                    {
+                        sum += weight * image.get(nx as usize, ny as usize);
+                    }
+                }
+                blurred.set(x, y, sum / total);
+            }
+        }
+        blurred
+    };
+    blur(&blur(image, true), false)
+}
@@ -0,0 +1,142 @@
+use crate::image_file;
+use crate::light_network::{self, LightLayer};
+use crate::preprocessing::Preprocessing;
+use glob::Pattern;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Csv,
+    JsonLines,
+}
+
+pub struct Prediction {
+    pub path: PathBuf,
+    pub probabilities: Vec<f64>,
+    //読み込みと前処理を除いた、順伝播だけに掛かった時間
+    pub inference_seconds: f64,
+}
+
+impl Prediction {
+    pub fn label(&self) -> usize {
+        self.top_k(1)[0].0
+    }
+
+    //確率の高い順にk個の(ラベル, 確率)
+    pub fn top_k(&self, k: usize) -> Vec<(usize, f64)> {
+        let mut indexed: Vec<(usize, f64)> =
+            self.probabilities.iter().copied().enumerate().collect();
+        indexed.sort_by(|a, b| b.1.total_cmp(&a.1));
+        indexed.truncate(k);
+        indexed
+    }
+}
+
+//dir_path以下を再帰的に探し、dir_pathからの相対パスがpatternに合うファイルを名前順に返す
+//ディレクトリへのシンボリックリンクは循環している事があるので辿らない
+pub fn find_files(dir_path: &Path, pattern: &Pattern) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir_path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if !path.is_dir() && pattern.matches_path(path.strip_prefix(dir_path).unwrap()) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn predict_file(
+    layers: &mut [LightLayer],
+    path: &Path,
+    preprocessing: &Preprocessing,
+) -> io::Result<Prediction> {
+    let image = image_file::load_model_input(path, preprocessing)?;
+    let start = Instant::now();
+    light_network::guess_answer(layers, &image);
+    let inference_seconds = start.elapsed().as_secs_f64();
+    Ok(Prediction {
+        path: path.to_path_buf(),
+        probabilities: layers.last().unwrap().get_activations(),
+        inference_seconds,
+    })
+}
+
+//判定結果を1件ずつ書き出す。途中で止まってもそこまでの結果は残る
+pub struct PredictionWriter<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    k: usize,
+}
+
+impl<W: Write> PredictionWriter<W> {
+    pub fn new(mut writer: W, format: OutputFormat, k: usize) -> io::Result<PredictionWriter<W>> {
+        if format == OutputFormat::Csv {
+            let mut header = String::from("path,label");
+            for rank in 1..=k {
+                header += &format!(",top{0}_label,top{0}_probability", rank);
+            }
+            writeln!(writer, "{},inference_ms", header)?;
+        }
+        Ok(PredictionWriter { writer, format, k })
+    }
+
+    pub fn write(&mut self, prediction: &Prediction) -> io::Result<()> {
+        let path = prediction.path.to_string_lossy();
+        let top_k = prediction.top_k(self.k);
+        let inference_ms = prediction.inference_seconds * 1000.0;
+        match self.format {
+            OutputFormat::Csv => {
+                //パスはカンマや引用符を含み得るので引用符で囲み、中の引用符は2つ重ねる
+                let mut line = format!("\"{}\",{}", path.replace('"', "\"\""), prediction.label());
+                for (label, probability) in top_k {
+                    line += &format!(",{},{}", label, probability);
+                }
+                writeln!(self.writer, "{},{}", line, inference_ms)?;
+            }
+            OutputFormat::JsonLines => {
+                let top_k: Vec<String> = top_k
+                    .iter()
+                    .map(|(label, probability)| {
+                        format!("{{\"label\":{},\"probability\":{}}}", label, probability)
+                    })
+                    .collect();
+                writeln!(
+                    self.writer,
+                    "{{\"path\":{},\"label\":{},\"top_k\":[{}],\"inference_ms\":{}}}",
+                    json_string(&path),
+                    prediction.label(),
+                    top_k.join(","),
+                    inference_ms
+                )?;
+            }
+        }
+        self.writer.flush()
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
@@ -1,6 +1,10 @@
+use crate::architecture::LayerSpec;
+use crate::light_network;
+use crate::network::{Layer, Normalization};
 use std::{
     fs::File,
     io::{Error, Read},
+    path::Path,
 };
 
 pub fn get_num_of_images(file: &mut File) -> Result<usize, Error> {
@@ -12,16 +16,21 @@ pub fn get_num_of_images(file: &mut File) -> Result<usize, Error> {
     Ok(number as usize)
 }
 
-pub fn get_next_image(file: &mut File) -> Result<Box<Vec<f64>>, Error> {
+pub fn get_next_image(file: &mut File) -> Result<Vec<f64>, Error> {
     let mut pixels = [0u8; 784];
-    let mut normalized_pixels = vec![0f64; 784];
     file.read_exact(&mut pixels)?;
+    Ok(normalize_image(&pixels))
+}
+
+//ファイルに書かれた784バイトの画像を転置して0~1の値にする
+pub fn normalize_image(pixels: &[u8]) -> Vec<f64> {
+    let mut normalized_pixels = vec![0f64; 784];
     for col in 0..28 {
         for row in 0..28 {
             normalized_pixels[col * 28 + row] = pixels[row * 28 + col] as f64 / 255f64;
         }
     }
-    Ok(Box::new(normalized_pixels))
+    normalized_pixels
 }
 
 pub fn get_num_of_labels(file: &mut File) -> Result<usize, Error> {
@@ -67,3 +76,64 @@ pub fn load_neuron(file: &mut File, shallower_size: usize) -> Result<(Vec<f64>,
     );
     Ok((weights, bias))
 }
+
+pub fn load_layer_normalization(file: &mut File, size: usize) -> Result<Vec<(f64, f64)>, Error> {
+    let mut datas: Vec<u8> = vec![0u8; 16 * size];
+    file.read_exact(&mut datas)?;
+    Ok(datas
+        .chunks_exact(16)
+        .map(|data| {
+            (
+                f64::from_be_bytes(data[..8].try_into().unwrap()),
+                f64::from_be_bytes(data[8..].try_into().unwrap()),
+            )
+        })
+        .collect())
+}
+
+//保存したモデルを続きから訓練できる形で読み込む
+//バッチ正規化は重みとバイアスに畳み込んで保存してあるので、正規化の無い全結合層として読み込む
+pub fn load_model(model_name: &str) -> Result<Vec<Layer>, Error> {
+    let model_path = Path::new("save_datas").join(model_name);
+    let mut architecture = light_network::load_architecture(&model_path)?;
+    for spec in &mut architecture.layers {
+        if let LayerSpec::Dense { normalization, .. } = spec {
+            if *normalization == Normalization::Batch {
+                *normalization = Normalization::None;
+            }
+        }
+    }
+    let input_shapes = architecture.input_shapes();
+    let mut layers = architecture.build();
+    for (i, (layer, shape)) in layers.iter_mut().zip(input_shapes).enumerate() {
+        let layer_path = model_path.join(format!("layer{}.bin", i));
+        match layer {
+            Layer::Dense(layer) => {
+                let mut file = File::open(layer_path)?;
+                for neuron in &mut layer.neurons {
+                    let (weights, bias) = load_neuron(&mut file, shape.size())?;
+                    neuron.set_parameters(weights, bias);
+                }
+                let norm_path = model_path.join(format!("layer{}_norm.bin", i));
+                if layer.normalization == Normalization::Layer || norm_path.exists() {
+                    layer.normalization = Normalization::Layer;
+                    let mut norm_file = File::open(norm_path)?;
+                    let parameters = load_layer_normalization(&mut norm_file, layer.neurons.len())?;
+                    for (neuron, (gamma, beta)) in layer.neurons.iter_mut().zip(parameters) {
+                        neuron.set_layer_normalization_parameters(gamma, beta);
+                    }
+                }
+            }
+            Layer::Convolution(layer) => {
+                let mut file = File::open(layer_path)?;
+                let size_filter = layer.settings.kernel_size.pow(2) * shape.channels;
+                for filter in &mut layer.filters {
+                    let (weights, bias) = load_neuron(&mut file, size_filter)?;
+                    filter.set_parameters(weights, bias);
+                }
+            }
+            Layer::Pooling(_) | Layer::Flatten(_) => {}
+        }
+    }
+    Ok(layers)
+}
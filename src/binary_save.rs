@@ -1,12 +1,24 @@
-use rand_distr::num_traits::ToBytes;
-
-use crate::network::Layer;
-use std::fs::{create_dir_all, File};
+use crate::architecture::{Architecture, LayerSpec};
+use crate::convolution::IMAGE_SHAPE;
+use crate::network::{Layer, Normalization};
+use std::fs::{self, create_dir_all, File};
 use std::io::{self, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+//architecture.txtに書く構造。ドロップアウトは訓練中にしか掛けないので保存しない
+pub fn saved_architecture(layers: &[Layer]) -> Architecture {
+    let mut architecture = Architecture::from_layers(IMAGE_SHAPE, layers);
+    for spec in &mut architecture.layers {
+        if let LayerSpec::Dense { dropout_rate, .. } = spec {
+            *dropout_rate = 0.0;
+        }
+    }
+    architecture
+}
 
+//保存したフォルダのパスを返す
 #[inline]
-pub fn save_model(layers: &[Layer], layer_sizes: &[usize]) -> io::Result<()> {
+pub fn save_model(layers: &[Layer]) -> io::Result<PathBuf> {
     let mut model_name = String::new();
     println!("保存するフォルダ名を決めて下さい。");
     loop {
@@ -25,25 +37,71 @@ pub fn save_model(layers: &[Layer], layer_sizes: &[usize]) -> io::Result<()> {
     //ヘッダー書式: レイヤー数,各層の大きさ浅い方から出力層まで
     let mut path = Path::new("save_datas").join(&model_name).join("header.bin");
     let mut header_file = File::create(&path)?;
-    header_file.write_all(&layer_sizes.len().to_be_bytes())?;
-    for &size in layer_sizes {
-        header_file.write_all(&size.to_be_bytes())?;
+    header_file.write_all(&layers.len().to_be_bytes())?;
+    for layer in layers {
+        header_file.write_all(&layer.output_size().to_be_bytes())?;
     }
 
-    // データ書式: 各バイアス,各重み行列
+    //構造の書式: Architectureの文字列表現
+    path = Path::new("save_datas")
+        .join(&model_name)
+        .join("architecture.txt");
+    fs::write(&path, format!("{}\n", saved_architecture(layers)))?;
+
+    // データ書式: 各ニューロン(フィルタ)の重み,バイアス
     for (i, layer) in layers.iter().enumerate() {
         path = Path::new("save_datas")
             .join(&model_name)
             .join(format!("layer{}.bin", i));
-        let mut file = File::create(&path)?;
-        for neuron in &layer.neurons {
-            let (weights, bias) = neuron.get_parameters();
-            for weight in weights {
-                file.write_all(&weight.to_be_bytes())?;
+        match layer {
+            Layer::Dense(layer) => {
+                let mut file = File::create(&path)?;
+                for neuron in &layer.neurons {
+                    let (weights, bias) = neuron.get_parameters(layer.normalization);
+                    for weight in weights {
+                        file.write_all(&weight.to_be_bytes())?;
+                    }
+                    file.write_all(&bias.to_be_bytes())?;
+                }
+
+                // レイヤー正規化の書式: 各ニューロンのgamma,beta
+                // バッチ正規化は重みとバイアスに畳み込んであるので保存しない
+                if layer.normalization == Normalization::Layer {
+                    path = Path::new("save_datas")
+                        .join(&model_name)
+                        .join(format!("layer{}_norm.bin", i));
+                    let mut file = File::create(&path)?;
+                    for neuron in &layer.neurons {
+                        let (gamma, beta) = neuron.get_layer_normalization_parameters();
+                        file.write_all(&gamma.to_be_bytes())?;
+                        file.write_all(&beta.to_be_bytes())?;
+                    }
+                }
+            }
+            Layer::Convolution(layer) => {
+                let mut file = File::create(&path)?;
+                for filter in &layer.filters {
+                    let (weights, bias) = filter.get_parameters();
+                    for weight in weights {
+                        file.write_all(&weight.to_be_bytes())?;
+                    }
+                    file.write_all(&bias.to_be_bytes())?;
+                }
             }
-            let _ = file.write_all(&bias.to_be_bytes());
+            Layer::Pooling(_) | Layer::Flatten(_) => {}
         }
     }
 
-    Ok(())
+    Ok(Path::new("save_datas").join(&model_name))
+}
+
+//binary_load::get_next_imageで読める形式(EMNISTと同じく転置した784バイト、character_inputの.binと同じ)で28x28の画像を保存する
+pub fn save_image(path: &Path, image: &[f64]) -> io::Result<()> {
+    let mut pixels = [0u8; 784];
+    for row in 0..28 {
+        for col in 0..28 {
+            pixels[col * 28 + row] = (image[row * 28 + col].clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+    File::create(path)?.write_all(&pixels)
 }
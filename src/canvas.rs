@@ -0,0 +1,378 @@
+use crate::binary_save;
+use crate::light_network::{self, LightLayer};
+use crate::preprocessing::Preprocessing;
+use crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, queue};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIZE: usize = 28;
+//ヘルプの2行と上の枠の分だけ下から描く
+const TOP: u16 = 3;
+//枠の右に確率を表示する列
+const INFO_COLUMN: u16 = SIZE as u16 * 2 + 4;
+const BAR_WIDTH: usize = 20;
+//画素の濃さを表す文字。1画素を2文字で描く
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+const HELP: [&str; 2] = [
+    "左ボタンで書く 右ボタンで消す / 矢印キーで移動 Spaceでペンの上げ下げ xで消しゴム",
+    "cで全て消す sでtest_imageに保存 qかEscで終了",
+];
+
+//ペンの状態。Up以外の時はカーソルを動かすとその軌跡に書く(消す)
+#[derive(Clone, Copy, PartialEq)]
+enum Pen {
+    Up,
+    Draw,
+    Erase,
+}
+
+//28x28のお絵描き帳。画素はモデルに入力する向きで、0~1の値で持つ
+struct Canvas {
+    pixels: Vec<f64>,
+    cursor: (usize, usize),
+    pen: Pen,
+    //マウスでドラッグ中なら前の位置
+    last_mouse: Option<(usize, usize)>,
+    message: String,
+}
+
+impl Default for Canvas {
+    fn default() -> Self {
+        Canvas {
+            pixels: vec![0f64; SIZE * SIZE],
+            cursor: (SIZE / 2, SIZE / 2),
+            pen: Pen::Up,
+            last_mouse: None,
+            message: String::new(),
+        }
+    }
+}
+
+impl Canvas {
+    //EMNISTの線の太さに近くなるよう、中心から離れるほど薄くなる3x3の筆で書く
+    fn stamp(&mut self, (x, y): (usize, usize)) {
+        for dy in -1i32..=1 {
+            for dx in -1i32..=1 {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if !(0..SIZE as i32).contains(&nx) || !(0..SIZE as i32).contains(&ny) {
+                    continue;
+                }
+                let value = 1.0 - (dx * dx + dy * dy) as f64 / 3.2;
+                let pixel = &mut self.pixels[ny as usize * SIZE + nx as usize];
+                *pixel = pixel.max(value);
+            }
+        }
+    }
+
+    fn erase(&mut self, (x, y): (usize, usize)) {
+        for ny in y.saturating_sub(1)..(y + 2).min(SIZE) {
+            for nx in x.saturating_sub(1)..(x + 2).min(SIZE) {
+                self.pixels[ny * SIZE + nx] = 0.0;
+            }
+        }
+    }
+
+    //fromからtoまでの線分上に筆を置いていく。速く動かしても線が途切れないようにする
+    fn line(&mut self, from: (usize, usize), to: (usize, usize), erase: bool) {
+        let (dx, dy) = (to.0 as f64 - from.0 as f64, to.1 as f64 - from.1 as f64);
+        let steps = dx.abs().max(dy.abs()) as usize;
+        for step in 0..=steps {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                step as f64 / steps as f64
+            };
+            let point = (
+                (from.0 as f64 + dx * t).round() as usize,
+                (from.1 as f64 + dy * t).round() as usize,
+            );
+            if erase {
+                self.erase(point);
+            } else {
+                self.stamp(point);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pixels.fill(0.0);
+    }
+
+    fn is_blank(&self) -> bool {
+        self.pixels.iter().all(|&value| value == 0.0)
+    }
+
+    //矢印キーでカーソルを動かし、ペンを下ろしていれば軌跡に書く
+    fn move_cursor(&mut self, dx: i32, dy: i32) {
+        let from = self.cursor;
+        self.cursor = (
+            (from.0 as i32 + dx).clamp(0, SIZE as i32 - 1) as usize,
+            (from.1 as i32 + dy).clamp(0, SIZE as i32 - 1) as usize,
+        );
+        if self.pen != Pen::Up {
+            self.line(from, self.cursor, self.pen == Pen::Erase);
+        }
+    }
+
+    fn set_pen(&mut self, pen: Pen) {
+        self.pen = if self.pen == pen { Pen::Up } else { pen };
+        match self.pen {
+            Pen::Draw => self.stamp(self.cursor),
+            Pen::Erase => self.erase(self.cursor),
+            Pen::Up => {}
+        }
+    }
+
+    //端末の位置を画素の位置にする。枠の外ならNone
+    fn cell_at(column: u16, row: u16) -> Option<(usize, usize)> {
+        let x = (column.checked_sub(1)? / 2) as usize;
+        let y = row.checked_sub(TOP)? as usize;
+        (x < SIZE && y < SIZE).then_some((x, y))
+    }
+
+    fn handle_mouse(&mut self, event: MouseEvent) {
+        let cell = Canvas::cell_at(event.column, event.row);
+        match (event.kind, cell) {
+            (
+                MouseEventKind::Down(button @ (MouseButton::Left | MouseButton::Right))
+                | MouseEventKind::Drag(button @ (MouseButton::Left | MouseButton::Right)),
+                Some(cell),
+            ) => {
+                let from = self.last_mouse.unwrap_or(cell);
+                self.line(from, cell, button == MouseButton::Right);
+                self.last_mouse = Some(cell);
+                self.cursor = cell;
+            }
+            _ => self.last_mouse = None,
+        }
+    }
+
+    //test_imageに日時(UTC)の名前で保存する
+    fn save(&self) -> io::Result<PathBuf> {
+        let dir_path = Path::new("test_image");
+        std::fs::create_dir_all(dir_path)?;
+        let name = timestamp();
+        let mut path = dir_path.join(format!("{}.bin", name));
+        let mut number = 1;
+        while path.exists() {
+            path = dir_path.join(format!("{}_{}.bin", name, number));
+            number += 1;
+        }
+        binary_save::save_image(&path, &self.pixels)?;
+        Ok(path)
+    }
+
+    fn render(&self, out: &mut impl Write, probabilities: Option<&[f64]>) -> io::Result<()> {
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+        for (i, line) in HELP.iter().enumerate() {
+            queue!(out, cursor::MoveTo(0, i as u16), Print(line))?;
+        }
+        let border = "─".repeat(SIZE * 2);
+        queue!(
+            out,
+            cursor::MoveTo(0, TOP - 1),
+            Print(format!("┌{}┐", border))
+        )?;
+        for y in 0..SIZE {
+            queue!(out, cursor::MoveTo(0, TOP + y as u16), Print('│'))?;
+            for x in 0..SIZE {
+                let value = self.pixels[y * SIZE + x];
+                let shade = SHADES[(value * (SHADES.len() - 1) as f64).round() as usize];
+                if (x, y) == self.cursor {
+                    queue!(
+                        out,
+                        SetAttribute(Attribute::Reverse),
+                        Print(format!("{}{}", shade, shade)),
+                        SetAttribute(Attribute::Reset)
+                    )?;
+                } else {
+                    queue!(out, Print(format!("{}{}", shade, shade)))?;
+                }
+            }
+            queue!(out, Print('│'))?;
+        }
+        queue!(
+            out,
+            cursor::MoveTo(0, TOP + SIZE as u16),
+            Print(format!("└{}┘", border))
+        )?;
+        let pen = match self.pen {
+            Pen::Up => "ペン: 上げている",
+            Pen::Draw => "ペン: 書く",
+            Pen::Erase => "ペン: 消しゴム",
+        };
+        queue!(out, cursor::MoveTo(INFO_COLUMN, TOP), Print(pen))?;
+        match probabilities {
+            Some(probabilities) => {
+                let answer = (0..probabilities.len())
+                    .max_by(|&a, &b| probabilities[a].total_cmp(&probabilities[b]))
+                    .unwrap_or(0);
+                queue!(
+                    out,
+                    cursor::MoveTo(INFO_COLUMN, TOP + 2),
+                    Print(format!("予測: {}", answer))
+                )?;
+                for (label, probability) in probabilities.iter().enumerate() {
+                    let width = (probability * BAR_WIDTH as f64).round() as usize;
+                    queue!(
+                        out,
+                        cursor::MoveTo(INFO_COLUMN, TOP + 4 + label as u16),
+                        Print(format!(
+                            "{}: {:<width$} {:>6.2} %",
+                            label,
+                            "█".repeat(width),
+                            probability * 100.0,
+                            width = BAR_WIDTH
+                        ))
+                    )?;
+                }
+            }
+            None => queue!(
+                out,
+                cursor::MoveTo(INFO_COLUMN, TOP + 2),
+                Print("数字を書いて下さい。")
+            )?,
+        }
+        queue!(
+            out,
+            cursor::MoveTo(0, TOP + SIZE as u16 + 1),
+            Print(&self.message)
+        )?;
+        out.flush()
+    }
+}
+
+//端末を元に戻す。途中でエラーになっても戻るようにDropで行う
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        let guard = TerminalGuard;
+        queue!(
+            io::stdout(),
+            EnterAlternateScreen,
+            EnableMouseCapture,
+            cursor::Hide
+        )?;
+        io::stdout().flush()?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = queue!(
+            io::stdout(),
+            cursor::Show,
+            DisableMouseCapture,
+            LeaveAlternateScreen
+        );
+        let _ = io::stdout().flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+//端末にお絵描き帳を表示し、書き換える度にlayersで判定し直す。マウスが使えない端末ではキーボードで書く
+pub fn run(layers: &mut [LightLayer], preprocessing: &Preprocessing) -> io::Result<()> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "標準入出力が端末でないので、お絵描き帳は使えません。",
+        ));
+    }
+    let _guard = TerminalGuard::enter()?;
+    let mut canvas = Canvas::default();
+    let mut out = io::stdout();
+    let mut probabilities: Option<Vec<f64>> = None;
+    loop {
+        canvas.render(&mut out, probabilities.as_deref())?;
+        let before = canvas.pixels.clone();
+        match event::read()? {
+            Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) => match code {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break,
+                KeyCode::Left | KeyCode::Char('h') => canvas.move_cursor(-1, 0),
+                KeyCode::Right | KeyCode::Char('l') => canvas.move_cursor(1, 0),
+                KeyCode::Up | KeyCode::Char('k') => canvas.move_cursor(0, -1),
+                KeyCode::Down | KeyCode::Char('j') => canvas.move_cursor(0, 1),
+                KeyCode::Char(' ') | KeyCode::Enter => canvas.set_pen(Pen::Draw),
+                KeyCode::Char('x') => canvas.set_pen(Pen::Erase),
+                KeyCode::Char('c') => {
+                    canvas.clear();
+                    canvas.message.clear();
+                }
+                KeyCode::Char('s') => {
+                    canvas.message = if canvas.is_blank() {
+                        "何も書かれていないので保存しませんでした。".to_string()
+                    } else {
+                        match canvas.save() {
+                            Ok(path) => format!("{}に保存しました。", path.display()),
+                            Err(x) => format!("保存中にエラーが発生しました: {}", x),
+                        }
+                    };
+                }
+                _ => {}
+            },
+            Event::Mouse(event) => canvas.handle_mouse(event),
+            _ => {}
+        }
+        if canvas.pixels != before {
+            probabilities = if canvas.is_blank() {
+                None
+            } else {
+                light_network::guess_answer(layers, &preprocessing.apply(&canvas.pixels));
+                Some(layers.last().unwrap().get_activations())
+            };
+        }
+    }
+    Ok(())
+}
+
+//UTCの日時をcharacter_inputのファイル名と同じ"年_月_日_時_分_秒"の形にする
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    //1970年1月1日からの日数をグレゴリオ暦の年月日にする
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}_{:02}_{:02}_{:02}_{:02}_{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,81 @@
+use crate::image::GrayImage;
+
+//3x5の数字のフォント。各行の下位3ビットが左から右の画素
+const DIGIT_FONT: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+const CHAR_WIDTH: usize = 4;
+//画像の下にラベルを書く帯の高さ
+const LABEL_HEIGHT: usize = 7;
+const GRID_BRIGHTNESS: f64 = 0.5;
+
+//画像をcolumns列の格子に並べ、それぞれの下にラベルを書いた1枚の画像にする
+//ラベルに書けるのは数字だけで、それ以外の文字は空白になる
+pub fn contact_sheet(tiles: &[(GrayImage, String)], columns: usize) -> GrayImage {
+    let tile_width = tiles
+        .iter()
+        .map(|(image, _)| image.width)
+        .max()
+        .unwrap_or(0);
+    let tile_height = tiles
+        .iter()
+        .map(|(image, _)| image.height)
+        .max()
+        .unwrap_or(0);
+    let columns = columns.clamp(1, tiles.len().max(1));
+    let rows = tiles.len().div_ceil(columns);
+    let cell_width = tile_width + 1;
+    let cell_height = tile_height + LABEL_HEIGHT + 1;
+    let mut sheet = GrayImage::new(columns * cell_width + 1, rows * cell_height + 1);
+    //格子の線
+    for y in 0..sheet.height {
+        for x in 0..sheet.width {
+            if x % cell_width == 0 || y % cell_height == 0 {
+                sheet.set(x, y, GRID_BRIGHTNESS);
+            }
+        }
+    }
+    for (i, (image, label)) in tiles.iter().enumerate() {
+        let left = (i % columns) * cell_width + 1;
+        let top = (i / columns) * cell_height + 1;
+        for y in 0..image.height {
+            for x in 0..image.width {
+                sheet.set(left + x, top + y, image.get(x, y));
+            }
+        }
+        draw_text(
+            &mut sheet,
+            left + 1,
+            top + tile_height + 1,
+            tile_width,
+            label,
+        );
+    }
+    sheet
+}
+
+//数字を3x5のフォントで書く。幅や画像に収まらない分は書かない
+pub fn draw_text(image: &mut GrayImage, left: usize, top: usize, width: usize, text: &str) {
+    for (i, c) in text.chars().take(width / CHAR_WIDTH).enumerate() {
+        let Some(digit) = c.to_digit(10) else {
+            continue;
+        };
+        for (dy, row) in DIGIT_FONT[digit as usize].iter().enumerate() {
+            for dx in 0..3 {
+                let (x, y) = (left + i * CHAR_WIDTH + dx, top + dy);
+                if row >> (2 - dx) & 1 == 1 && x < image.width && y < image.height {
+                    image.set(x, y, 1.0);
+                }
+            }
+        }
+    }
+}
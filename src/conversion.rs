@@ -0,0 +1,88 @@
+use crate::dataset::{Dataset, DatasetSource, IDX_IMAGE_MAGIC, IDX_LABEL_MAGIC};
+use crate::image::GrayImage;
+use crate::image_file;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+//datasetの全ての画像とラベルをtargetの形式で書き出し、書き出した数を返す。
+//transposeならEMNISTの.binファイルと同じく縦横を入れ替えた向きで書く
+//(IDX形式をこのプログラムで読むと転置し直されるので、元と同じ向きに戻る)
+pub fn convert(
+    dataset: &dyn Dataset,
+    target: &DatasetSource,
+    transpose: bool,
+) -> io::Result<usize> {
+    let shape = dataset.shape();
+    let (height, width) = if transpose {
+        (shape.width, shape.height)
+    } else {
+        (shape.height, shape.width)
+    };
+    match target {
+        DatasetSource::Idx {
+            image_path,
+            label_path,
+        } => {
+            let mut images = BufWriter::new(File::create(image_path)?);
+            let mut labels = BufWriter::new(File::create(label_path)?);
+            let count = dataset.len() as u32;
+            for value in [IDX_IMAGE_MAGIC, count, height as u32, width as u32] {
+                images.write_all(&value.to_be_bytes())?;
+            }
+            for value in [IDX_LABEL_MAGIC, count] {
+                labels.write_all(&value.to_be_bytes())?;
+            }
+            for index in 0..dataset.len() {
+                let (image, label) = dataset.get(index)?;
+                images.write_all(&to_bytes(&image, shape.width, shape.height, transpose))?;
+                labels.write_all(&[label])?;
+            }
+            images.flush()?;
+            labels.flush()?;
+        }
+        DatasetSource::Csv(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            //Kaggleの形式と同じ見出しの行
+            let header: Vec<String> = (0..height * width).map(|i| format!("pixel{}", i)).collect();
+            writeln!(file, "label,{}", header.join(","))?;
+            for index in 0..dataset.len() {
+                let (image, label) = dataset.get(index)?;
+                let pixels: Vec<String> = to_bytes(&image, shape.width, shape.height, transpose)
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect();
+                writeln!(file, "{},{}", label, pixels.join(","))?;
+            }
+            file.flush()?;
+        }
+        //ラベル毎のサブフォルダに番号.pngで書く
+        DatasetSource::ImageFolder(path) => {
+            for index in 0..dataset.len() {
+                let (image, label) = dataset.get(index)?;
+                let label_path = path.join(label.to_string());
+                fs::create_dir_all(&label_path)?;
+                let pixels = to_bytes(&image, shape.width, shape.height, transpose)
+                    .iter()
+                    .map(|&value| value as f64 / 255.0)
+                    .collect();
+                image_file::save_image(
+                    &label_path.join(format!("{}.png", index)),
+                    &GrayImage::from_pixels(width, height, pixels),
+                )?;
+            }
+        }
+    }
+    Ok(dataset.len())
+}
+
+//0~1の画像を0~255の1画素1バイトにする。transposeなら縦横を入れ替える
+fn to_bytes(image: &[f64], width: usize, height: usize, transpose: bool) -> Vec<u8> {
+    let byte = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    if transpose {
+        (0..width)
+            .flat_map(|x| (0..height).map(move |y| byte(image[y * width + x])))
+            .collect()
+    } else {
+        image.iter().map(|&value| byte(value)).collect()
+    }
+}
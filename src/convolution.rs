@@ -0,0 +1,438 @@
+use crate::network::Activation;
+use crate::regularization::Regularization;
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+
+//画像や特徴マップの形。データはチャンネル,行,列の順に平たく並べる
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Shape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Shape {
+    pub fn new(channels: usize, height: usize, width: usize) -> Shape {
+        Shape {
+            channels,
+            height,
+            width,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    #[inline]
+    fn index(&self, channel: usize, y: usize, x: usize) -> usize {
+        (channel * self.height + y) * self.width + x
+    }
+}
+
+//EMNISTの画像の形
+pub const IMAGE_SHAPE: Shape = Shape {
+    channels: 1,
+    height: 28,
+    width: 28,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct ConvolutionSettings {
+    pub kernel_size: usize,
+    pub stride: usize,
+    pub padding: usize,
+}
+
+impl ConvolutionSettings {
+    //出力の縦横が1未満になる設定ならNone
+    pub fn output_shape(&self, input_shape: Shape, num_filters: usize) -> Option<Shape> {
+        let height = input_shape.height + 2 * self.padding;
+        let width = input_shape.width + 2 * self.padding;
+        if self.stride == 0 || height < self.kernel_size || width < self.kernel_size {
+            return None;
+        }
+        Some(Shape::new(
+            num_filters,
+            (height - self.kernel_size) / self.stride + 1,
+            (width - self.kernel_size) / self.stride + 1,
+        ))
+    }
+
+    //フィルタの(入力チャンネル,カーネル内の行,列)と出力の位置(oy,ox)に対応する入力の添字
+    #[inline]
+    fn input_index(
+        &self,
+        input_shape: Shape,
+        channel: usize,
+        ky: usize,
+        kx: usize,
+        oy: usize,
+        ox: usize,
+    ) -> Option<usize> {
+        let y = (oy * self.stride + ky).checked_sub(self.padding)?;
+        let x = (ox * self.stride + kx).checked_sub(self.padding)?;
+        if y < input_shape.height && x < input_shape.width {
+            Some(input_shape.index(channel, y, x))
+        } else {
+            None
+        }
+    }
+}
+
+//1枚分の畳み込み。活性化関数は掛けない
+pub fn convolve(
+    input: &[f64],
+    input_shape: Shape,
+    output_shape: Shape,
+    settings: ConvolutionSettings,
+    filters: &[(&[f64], f64)],
+) -> Vec<f64> {
+    let k = settings.kernel_size;
+    let mut output = vec![0f64; output_shape.size()];
+    for (filter_i, (weights, bias)) in filters.iter().enumerate() {
+        for oy in 0..output_shape.height {
+            for ox in 0..output_shape.width {
+                let mut sum = *bias;
+                for channel in 0..input_shape.channels {
+                    for ky in 0..k {
+                        for kx in 0..k {
+                            if let Some(i) =
+                                settings.input_index(input_shape, channel, ky, kx, oy, ox)
+                            {
+                                sum += weights[(channel * k + ky) * k + kx] * input[i];
+                            }
+                        }
+                    }
+                }
+                output[output_shape.index(filter_i, oy, ox)] = sum;
+            }
+        }
+    }
+    output
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pooling {
+    Max,
+    Average,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct PoolingSettings {
+    pub kind: Pooling,
+    pub size: usize,
+    pub stride: usize,
+}
+
+impl PoolingSettings {
+    pub fn output_shape(&self, input_shape: Shape) -> Option<Shape> {
+        if self.stride == 0
+            || self.size == 0
+            || input_shape.height < self.size
+            || input_shape.width < self.size
+        {
+            return None;
+        }
+        Some(Shape::new(
+            input_shape.channels,
+            (input_shape.height - self.size) / self.stride + 1,
+            (input_shape.width - self.size) / self.stride + 1,
+        ))
+    }
+
+    //出力の各要素について、値と最大値を取った入力の添字を返す
+    pub fn pool(
+        &self,
+        input: &[f64],
+        input_shape: Shape,
+        output_shape: Shape,
+    ) -> (Vec<f64>, Vec<usize>) {
+        let mut output = vec![0f64; output_shape.size()];
+        let mut max_indices = vec![0usize; output_shape.size()];
+        for channel in 0..output_shape.channels {
+            for oy in 0..output_shape.height {
+                for ox in 0..output_shape.width {
+                    let o = output_shape.index(channel, oy, ox);
+                    let mut max_i = input_shape.index(channel, oy * self.stride, ox * self.stride);
+                    let mut sum = 0f64;
+                    for py in 0..self.size {
+                        for px in 0..self.size {
+                            let i = input_shape.index(
+                                channel,
+                                oy * self.stride + py,
+                                ox * self.stride + px,
+                            );
+                            sum += input[i];
+                            if input[i] > input[max_i] {
+                                max_i = i;
+                            }
+                        }
+                    }
+                    output[o] = match self.kind {
+                        Pooling::Max => input[max_i],
+                        Pooling::Average => sum / (self.size * self.size) as f64,
+                    };
+                    max_indices[o] = max_i;
+                }
+            }
+        }
+        (output, max_indices)
+    }
+}
+
+pub struct ConvolutionLayer {
+    pub filters: Vec<Filter>,
+    pub settings: ConvolutionSettings,
+    pub activation: Activation,
+    input_shape: Shape,
+    output_shape: Shape,
+    //以下はミニバッチ内のサンプル毎の値
+    pre_activations: Vec<Vec<f64>>,
+    activations: Vec<Vec<f64>>,
+}
+
+impl ConvolutionLayer {
+    pub fn new(
+        num_filters: usize,
+        settings: ConvolutionSettings,
+        input_shape: Shape,
+        rng: &mut impl Rng,
+    ) -> Option<ConvolutionLayer> {
+        let output_shape = settings.output_shape(input_shape, num_filters)?;
+        let size_filter = input_shape.channels * settings.kernel_size * settings.kernel_size;
+        Some(ConvolutionLayer {
+            filters: (0..num_filters)
+                .map(|_| Filter::new_he(size_filter, rng))
+                .collect(),
+            settings,
+            activation: Activation::LeakyRelu,
+            input_shape,
+            output_shape,
+            pre_activations: Vec::new(),
+            activations: Vec::new(),
+        })
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input_shape
+    }
+
+    pub fn output_shape(&self) -> Shape {
+        self.output_shape
+    }
+
+    pub fn set_activations(&mut self, ref_shallower_activations: &[Vec<f64>]) {
+        let filters: Vec<(&[f64], f64)> = self
+            .filters
+            .iter()
+            .map(|filter| (&filter.weights[..], filter.bias))
+            .collect();
+        self.pre_activations = ref_shallower_activations
+            .iter()
+            .map(|input| {
+                convolve(
+                    input,
+                    self.input_shape,
+                    self.output_shape,
+                    self.settings,
+                    &filters,
+                )
+            })
+            .collect();
+        self.activations = self
+            .pre_activations
+            .iter()
+            .map(|sample| sample.iter().map(|&x| self.activation.apply(x)).collect())
+            .collect();
+    }
+
+    pub fn get_activations(&self) -> Vec<Vec<f64>> {
+        self.activations.clone()
+    }
+
+    //deltasはこの層の出力についての損失の勾配。浅い層の出力についての勾配を返す
+    pub fn stack_corrections(
+        &mut self,
+        ref_shallower_activations: &[Vec<f64>],
+        deltas: &[Vec<f64>],
+    ) -> Vec<Vec<f64>> {
+        let k = self.settings.kernel_size;
+        let mut shallower_deltas = vec![vec![0f64; self.input_shape.size()]; deltas.len()];
+        for (((input, sample_deltas), pre_activations), sample_shallower_deltas) in
+            ref_shallower_activations
+                .iter()
+                .zip(deltas)
+                .zip(&self.pre_activations)
+                .zip(shallower_deltas.iter_mut())
+        {
+            for (filter_i, filter) in self.filters.iter_mut().enumerate() {
+                for oy in 0..self.output_shape.height {
+                    for ox in 0..self.output_shape.width {
+                        let o = self.output_shape.index(filter_i, oy, ox);
+                        let delta =
+                            sample_deltas[o] * self.activation.derivative(pre_activations[o]);
+                        if delta == 0.0 {
+                            continue;
+                        }
+                        for channel in 0..self.input_shape.channels {
+                            for ky in 0..k {
+                                for kx in 0..k {
+                                    if let Some(i) = self.settings.input_index(
+                                        self.input_shape,
+                                        channel,
+                                        ky,
+                                        kx,
+                                        oy,
+                                        ox,
+                                    ) {
+                                        let w = (channel * k + ky) * k + kx;
+                                        filter.fix_weights[w] += delta * input[i];
+                                        sample_shallower_deltas[i] += delta * filter.weights[w];
+                                    }
+                                }
+                            }
+                        }
+                        filter.fix_bias += delta;
+                    }
+                }
+            }
+        }
+        shallower_deltas
+    }
+}
+
+//畳み込み層のフィルタ1枚分。全結合層のNeuronに相当する
+pub struct Filter {
+    weights: Vec<f64>,
+    fix_weights: Vec<f64>,
+    bias: f64,
+    fix_bias: f64,
+}
+
+impl Filter {
+    fn new_he(size_filter: usize, rng: &mut impl Rng) -> Filter {
+        let normal = Normal::new(0f64, (2.0 / size_filter as f64).sqrt()).unwrap();
+        Filter {
+            weights: (0..size_filter).map(|_| normal.sample(rng)).collect(),
+            fix_weights: vec![0f64; size_filter],
+            bias: 0f64,
+            fix_bias: 0f64,
+        }
+    }
+
+    pub fn apply_fixes(
+        &mut self,
+        size_batch: usize,
+        learning_rate: f64,
+        regularization: &Regularization,
+    ) {
+        let step = learning_rate / size_batch as f64;
+        for (weight, fix_weight) in self.weights.iter_mut().zip(&mut self.fix_weights) {
+            *weight -= step * *fix_weight + learning_rate * regularization.gradient(*weight);
+            *fix_weight = 0f64;
+        }
+        self.bias -= step * self.fix_bias + learning_rate * regularization.bias_gradient(self.bias);
+        self.fix_bias = 0f64;
+        regularization.constrain(&mut self.weights);
+    }
+
+    //各パラメータと溜まった勾配の組
+    pub fn parameters_mut(&mut self) -> impl Iterator<Item = (&mut f64, &mut f64)> {
+        self.weights
+            .iter_mut()
+            .zip(&mut self.fix_weights)
+            .chain([(&mut self.bias, &mut self.fix_bias)])
+    }
+
+    pub fn penalty(&self, regularization: &Regularization) -> f64 {
+        regularization.penalty(&self.weights, self.bias)
+    }
+
+    pub fn get_parameters(&self) -> (&Vec<f64>, f64) {
+        (&self.weights, self.bias)
+    }
+
+    pub fn set_parameters(&mut self, weights: Vec<f64>, bias: f64) {
+        self.weights = weights;
+        self.bias = bias;
+    }
+}
+
+pub struct PoolingLayer {
+    pub settings: PoolingSettings,
+    input_shape: Shape,
+    output_shape: Shape,
+    max_indices: Vec<Vec<usize>>,
+    activations: Vec<Vec<f64>>,
+}
+
+impl PoolingLayer {
+    pub fn new(settings: PoolingSettings, input_shape: Shape) -> Option<PoolingLayer> {
+        Some(PoolingLayer {
+            settings,
+            input_shape,
+            output_shape: settings.output_shape(input_shape)?,
+            max_indices: Vec::new(),
+            activations: Vec::new(),
+        })
+    }
+
+    pub fn input_shape(&self) -> Shape {
+        self.input_shape
+    }
+
+    pub fn output_shape(&self) -> Shape {
+        self.output_shape
+    }
+
+    pub fn set_activations(&mut self, ref_shallower_activations: &[Vec<f64>]) {
+        (self.activations, self.max_indices) = ref_shallower_activations
+            .iter()
+            .map(|input| {
+                self.settings
+                    .pool(input, self.input_shape, self.output_shape)
+            })
+            .unzip();
+    }
+
+    pub fn get_activations(&self) -> Vec<Vec<f64>> {
+        self.activations.clone()
+    }
+
+    pub fn stack_corrections(&self, deltas: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        let mut shallower_deltas = vec![vec![0f64; self.input_shape.size()]; deltas.len()];
+        let window = (self.settings.size * self.settings.size) as f64;
+        for ((sample_deltas, max_indices), sample_shallower_deltas) in deltas
+            .iter()
+            .zip(&self.max_indices)
+            .zip(shallower_deltas.iter_mut())
+        {
+            for channel in 0..self.output_shape.channels {
+                for oy in 0..self.output_shape.height {
+                    for ox in 0..self.output_shape.width {
+                        let o = self.output_shape.index(channel, oy, ox);
+                        match self.settings.kind {
+                            Pooling::Max => {
+                                sample_shallower_deltas[max_indices[o]] += sample_deltas[o]
+                            }
+                            Pooling::Average => {
+                                for py in 0..self.settings.size {
+                                    for px in 0..self.settings.size {
+                                        let i = self.input_shape.index(
+                                            channel,
+                                            oy * self.settings.stride + py,
+                                            ox * self.settings.stride + px,
+                                        );
+                                        sample_shallower_deltas[i] += sample_deltas[o] / window;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        shallower_deltas
+    }
+}
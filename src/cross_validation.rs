@@ -0,0 +1,122 @@
+use crate::architecture::Architecture;
+use crate::dataset::{Dataset, Subset};
+use crate::input_normalization::{InputNormalization, NormalizationKind};
+use crate::training::{self, TrainingSettings};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::io;
+
+//ラベル毎に混ぜてから順番に配り、どのフォールドも各ラベルの割合が全体とほぼ同じになるようにする
+pub fn stratified_folds(labels: &[u8], num_folds: usize, rng: &mut impl Rng) -> Vec<Vec<usize>> {
+    let num_classes = labels
+        .iter()
+        .map(|&label| label as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let mut classes: Vec<Vec<usize>> = vec![Vec::new(); num_classes];
+    for (i, &label) in labels.iter().enumerate() {
+        classes[label as usize].push(i);
+    }
+    let mut folds: Vec<Vec<usize>> = vec![Vec::new(); num_folds];
+    //前のラベルの続きから配ってフォールドの大きさの差を1以内にする
+    let mut fold_i = 0;
+    for mut indices in classes {
+        indices.shuffle(rng);
+        for index in indices {
+            folds[fold_i].push(index);
+            fold_i = (fold_i + 1) % num_folds;
+        }
+    }
+    folds
+}
+
+pub struct FoldResult {
+    pub train_loss: f64,
+    pub train_accuracy: f64,
+    pub validation_loss: f64,
+    pub validation_accuracy: f64,
+    pub elapsed_seconds: f64,
+}
+
+impl FoldResult {
+    pub const METRIC_NAMES: [&'static str; 5] = [
+        "train cost",
+        "train accuracy",
+        "validation cost",
+        "validation accuracy",
+        "elapsed seconds",
+    ];
+
+    pub fn metrics(&self) -> [f64; 5] {
+        [
+            self.train_loss,
+            self.train_accuracy,
+            self.validation_loss,
+            self.validation_accuracy,
+            self.elapsed_seconds,
+        ]
+    }
+}
+
+//フォールド毎に新しいネットワークを同じ設定で訓練し、残りのフォールドで訓練したモデルをそのフォールドで検証する
+//入力の正規化の統計量は検証するフォールドが混ざらないよう、フォールド毎に訓練に使う部分だけから求めてsettingsに入れる
+pub fn cross_validate(
+    architecture: &Architecture,
+    settings: &mut TrainingSettings,
+    normalization_kind: NormalizationKind,
+    dataset: &dyn Dataset,
+    num_folds: usize,
+) -> io::Result<Vec<FoldResult>> {
+    let folds = stratified_folds(&dataset.labels(), num_folds, &mut rand::thread_rng());
+    let mut results = Vec::with_capacity(num_folds);
+    for (fold_i, validation_indices) in folds.iter().enumerate() {
+        println!("fold: {}/{}", fold_i + 1, num_folds);
+        let train_indices: Vec<usize> = folds
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != fold_i)
+            .flat_map(|(_, fold)| fold.iter().copied())
+            .collect();
+        let train = Subset::new(dataset, train_indices);
+        settings.normalization = InputNormalization::fit(normalization_kind, &train)?;
+        let mut layers = architecture.build();
+        let history = training::train(
+            &mut layers,
+            &train,
+            &Subset::new(dataset, validation_indices.clone()),
+            settings,
+        )?;
+        let last_epoch = history.epochs.last().unwrap();
+        let result = FoldResult {
+            train_loss: last_epoch.loss,
+            train_accuracy: last_epoch.accuracy,
+            validation_loss: last_epoch.validation_loss.unwrap_or(f64::NAN),
+            validation_accuracy: last_epoch.validation_accuracy.unwrap_or(f64::NAN),
+            elapsed_seconds: last_epoch.elapsed_seconds,
+        };
+        results.push(result);
+    }
+    Ok(results)
+}
+
+//各指標の平均と標準偏差(不偏分散の平方根)
+pub fn summarize(results: &[FoldResult]) -> Vec<(&'static str, f64, f64)> {
+    let count = results.len() as f64;
+    FoldResult::METRIC_NAMES
+        .iter()
+        .enumerate()
+        .map(|(metric_i, &name)| {
+            let values: Vec<f64> = results
+                .iter()
+                .map(|result| result.metrics()[metric_i])
+                .collect();
+            let mean = values.iter().sum::<f64>() / count;
+            let variance = if results.len() > 1 {
+                values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.0)
+            } else {
+                0.0
+            };
+            (name, mean, variance.sqrt())
+        })
+        .collect()
+}
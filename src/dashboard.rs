@@ -0,0 +1,272 @@
+use crate::history::{EpochRecord, IterationRecord};
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+//訓練中の表示の仕方
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProgressDisplay {
+    //端末の数行を書き換え続ける
+    Dashboard,
+    //標準出力が端末でない時などの為に、ログとして残る行だけを出力する
+    Lines,
+    //何も表示しない
+    Quiet,
+}
+
+impl ProgressDisplay {
+    //標準出力が端末でなければダッシュボードは使わない
+    pub fn available(self) -> ProgressDisplay {
+        if self == ProgressDisplay::Dashboard && !io::stdout().is_terminal() {
+            ProgressDisplay::Lines
+        } else {
+            self
+        }
+    }
+}
+
+const BAR_WIDTH: usize = 30;
+const SPARKLINE_WIDTH: usize = 50;
+const SPARKLINE_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+//訓練データの損失の指数移動平均の係数
+const SMOOTHING: f64 = 0.05;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+//Linesの時に1エポックで出力する途中経過の行数
+const NUM_LOG_LINES: usize = 10;
+
+pub struct Dashboard {
+    display: ProgressDisplay,
+    num_epoch: usize,
+    num_iteration: usize,
+    size_batch: usize,
+    start: Instant,
+    last_draw: Option<Instant>,
+    num_drawn_lines: usize,
+    smoothed_loss: Option<f64>,
+    smoothed_losses: Vec<f64>,
+    validation_losses: Vec<f64>,
+    //現在のエポックの正答率の合計とイテレーション数
+    epoch_accuracy_sum: f64,
+    epoch_count: usize,
+}
+
+impl Dashboard {
+    pub fn new(
+        display: ProgressDisplay,
+        num_epoch: usize,
+        num_iteration: usize,
+        size_batch: usize,
+    ) -> Dashboard {
+        Dashboard {
+            display: display.available(),
+            num_epoch,
+            num_iteration,
+            size_batch,
+            start: Instant::now(),
+            last_draw: None,
+            num_drawn_lines: 0,
+            smoothed_loss: None,
+            smoothed_losses: Vec::new(),
+            validation_losses: Vec::new(),
+            epoch_accuracy_sum: 0.0,
+            epoch_count: 0,
+        }
+    }
+
+    pub fn update_iteration(&mut self, record: &IterationRecord) {
+        let smoothed = match self.smoothed_loss {
+            Some(smoothed) if record.loss.is_finite() => {
+                smoothed + SMOOTHING * (record.loss - smoothed)
+            }
+            Some(smoothed) => smoothed,
+            None => record.loss,
+        };
+        self.smoothed_loss = Some(smoothed);
+        self.smoothed_losses.push(smoothed);
+        self.epoch_accuracy_sum += record.accuracy;
+        self.epoch_count += 1;
+        match self.display {
+            ProgressDisplay::Dashboard => {
+                let is_last = record.iteration + 1 == self.num_iteration;
+                if is_last
+                    || self
+                        .last_draw
+                        .is_none_or(|t| t.elapsed() >= REDRAW_INTERVAL)
+                {
+                    self.draw(record);
+                }
+            }
+            ProgressDisplay::Lines => {
+                let interval = (self.num_iteration / NUM_LOG_LINES).max(1);
+                if (record.iteration + 1) % interval == 0 {
+                    println!(
+                        "epoch: {}/{}\titeration: {}/{}\tcost: {:.4}\taccuracy: {:.1}%\tlearning rate: {:.8}\t{:.1} samples/s\tETA: {}",
+                        record.epoch + 1,
+                        self.num_epoch,
+                        record.iteration + 1,
+                        self.num_iteration,
+                        smoothed,
+                        100.0 * self.running_accuracy(),
+                        record.learning_rate,
+                        self.samples_per_second(record),
+                        format_duration(self.eta(record))
+                    );
+                }
+            }
+            ProgressDisplay::Quiet => {}
+        }
+    }
+
+    pub fn finish_epoch(&mut self, record: &EpochRecord) {
+        if let Some(loss) = record.validation_loss {
+            self.validation_losses.push(loss);
+        }
+        self.epoch_accuracy_sum = 0.0;
+        self.epoch_count = 0;
+        if self.display == ProgressDisplay::Quiet {
+            return;
+        }
+        let mut summary = format!(
+            "epoch: {}/{}\tcost: {:.4}\taccuracy: {:.1}%",
+            record.epoch + 1,
+            self.num_epoch,
+            record.loss,
+            100.0 * record.accuracy
+        );
+        if let (Some(loss), Some(accuracy)) = (record.validation_loss, record.validation_accuracy) {
+            summary += &format!(
+                "\tvalidation cost: {:.4}\tvalidation accuracy: {:.1}%",
+                loss,
+                100.0 * accuracy
+            );
+        }
+        if self.display == ProgressDisplay::Lines {
+            println!("{}", summary);
+            return;
+        }
+        //ダッシュボードの上に結果を残してから描き直す
+        self.move_to_top();
+        println!("\x1b[K{}", summary);
+        if record.epoch + 1 < self.num_epoch {
+            self.num_drawn_lines = 0;
+            self.last_draw = None;
+        } else {
+            self.clear_below();
+        }
+    }
+
+    fn running_accuracy(&self) -> f64 {
+        self.epoch_accuracy_sum / self.epoch_count.max(1) as f64
+    }
+
+    fn done_iterations(&self, record: &IterationRecord) -> usize {
+        record.epoch * self.num_iteration + record.iteration + 1
+    }
+
+    fn samples_per_second(&self, record: &IterationRecord) -> f64 {
+        (self.done_iterations(record) * self.size_batch) as f64 / record.elapsed_seconds.max(1e-9)
+    }
+
+    fn eta(&self, record: &IterationRecord) -> f64 {
+        let done = self.done_iterations(record);
+        let remaining = self.num_epoch * self.num_iteration - done;
+        record.elapsed_seconds / done as f64 * remaining as f64
+    }
+
+    fn draw(&mut self, record: &IterationRecord) {
+        let done = self.done_iterations(record);
+        let total = self.num_epoch * self.num_iteration;
+        let progress = done as f64 / total as f64;
+        let filled = (progress * BAR_WIDTH as f64).round() as usize;
+        let lines = [
+            format!(
+                "epoch {}/{} [{}{}] {:5.1}%  経過 {}  残り {}  {:.1} samples/s",
+                record.epoch + 1,
+                self.num_epoch,
+                "█".repeat(filled),
+                "░".repeat(BAR_WIDTH - filled),
+                100.0 * progress,
+                format_duration(self.start.elapsed().as_secs_f64()),
+                format_duration(self.eta(record)),
+                self.samples_per_second(record)
+            ),
+            format!(
+                "learning rate: {:.8}  accuracy: {:.1}%  gradient norm: {:.4}",
+                record.learning_rate,
+                100.0 * self.running_accuracy(),
+                record.gradient_norm
+            ),
+            format!(
+                "train loss {:>8.4} {}",
+                self.smoothed_loss.unwrap_or(f64::NAN),
+                sparkline(&self.smoothed_losses, SPARKLINE_WIDTH)
+            ),
+            format!(
+                "valid loss {:>8} {}",
+                self.validation_losses
+                    .last()
+                    .map_or("-".to_string(), |loss| format!("{:.4}", loss)),
+                sparkline(&self.validation_losses, SPARKLINE_WIDTH)
+            ),
+        ];
+        self.move_to_top();
+        for line in &lines {
+            println!("\x1b[K{}", line);
+        }
+        let _ = io::stdout().flush();
+        self.num_drawn_lines = lines.len();
+        self.last_draw = Some(Instant::now());
+    }
+
+    //前に描いたダッシュボードの1行目へ戻る
+    fn move_to_top(&self) {
+        if self.num_drawn_lines > 0 {
+            print!("\x1b[{}F", self.num_drawn_lines);
+        }
+    }
+
+    fn clear_below(&mut self) {
+        print!("\x1b[J");
+        let _ = io::stdout().flush();
+        self.num_drawn_lines = 0;
+    }
+}
+
+//値の並びを幅widthに収まるよう区間毎に平均してブロック文字で表す
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    let values: Vec<f64> = values.iter().copied().filter(|x| x.is_finite()).collect();
+    if values.is_empty() {
+        return String::new();
+    }
+    let num_buckets = values.len().min(width);
+    let buckets: Vec<f64> = (0..num_buckets)
+        .map(|i| {
+            let begin = i * values.len() / num_buckets;
+            let end = ((i + 1) * values.len() / num_buckets).max(begin + 1);
+            values[begin..end].iter().sum::<f64>() / (end - begin) as f64
+        })
+        .collect();
+    let min = buckets.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = buckets.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let top = (SPARKLINE_LEVELS.len() - 1) as f64;
+    buckets
+        .iter()
+        .map(|x| {
+            let level = if max > min {
+                ((x - min) / (max - min) * top).round() as usize
+            } else {
+                0
+            };
+            SPARKLINE_LEVELS[level]
+        })
+        .collect()
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
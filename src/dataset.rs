@@ -0,0 +1,627 @@
+use crate::binary_load;
+use crate::convolution::{Shape, IMAGE_SHAPE};
+use crate::image_file;
+use crate::preprocessing::Preprocessing;
+use memmap2::Mmap;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const IMAGE_SIZE: usize = 28;
+const IMAGE_BYTES: usize = IMAGE_SIZE * IMAGE_SIZE;
+const IMAGE_HEADER_BYTES: u64 = 16;
+//IDX形式の先頭4バイト(0,0,型(0x08=u8),次元数)
+pub const IDX_IMAGE_MAGIC: u32 = 0x0000_0803;
+pub const IDX_LABEL_MAGIC: u32 = 0x0000_0801;
+
+//画像とラベルの組の集まり。画像はモデルに入力する向きと前処理を掛けた0~1の値で返す
+pub trait Dataset {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn image(&self, index: usize) -> io::Result<Vec<f64>>;
+
+    //ラベルは全てメモリに持つので失敗しない
+    fn label(&self, index: usize) -> u8;
+
+    fn get(&self, index: usize) -> io::Result<(Vec<f64>, u8)> {
+        Ok((self.image(index)?, self.label(index)))
+    }
+
+    fn shape(&self) -> Shape;
+
+    //ラベルの最大値+1
+    fn num_classes(&self) -> usize;
+
+    fn labels(&self) -> Vec<u8> {
+        (0..self.len()).map(|index| self.label(index)).collect()
+    }
+}
+
+fn num_classes(labels: &[u8]) -> usize {
+    labels
+        .iter()
+        .map(|&label| label as usize + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+//データセットの場所と形式
+pub enum DatasetSource {
+    Idx {
+        image_path: PathBuf,
+        label_path: PathBuf,
+    },
+    ImageFolder(PathBuf),
+    Csv(PathBuf),
+}
+
+impl DatasetSource {
+    //読み書きするファイルかディレクトリ
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            DatasetSource::Idx {
+                image_path,
+                label_path,
+            } => vec![image_path, label_path],
+            DatasetSource::ImageFolder(path) | DatasetSource::Csv(path) => vec![path],
+        }
+    }
+
+    //modeはIDX形式の時だけ使う
+    pub fn open(
+        &self,
+        mode: LoadMode,
+        preprocessing: Preprocessing,
+    ) -> io::Result<Box<dyn Dataset>> {
+        Ok(match self {
+            DatasetSource::Idx {
+                image_path,
+                label_path,
+            } => {
+                let mut dataset = IdxDataset::open(image_path, label_path, mode)?;
+                dataset.preprocessing = preprocessing;
+                Box::new(dataset)
+            }
+            DatasetSource::ImageFolder(path) => {
+                let mut dataset = ImageFolderDataset::open(path)?;
+                dataset.preprocessing = preprocessing;
+                Box::new(dataset)
+            }
+            DatasetSource::Csv(path) => {
+                let mut dataset = CsvDataset::open(path)?;
+                dataset.preprocessing = preprocessing;
+                Box::new(dataset)
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LoadMode {
+    //全ての画像を1画素1バイトのままメモリに読み込む
+    Memory,
+    //画像のファイルをメモリマップし、読み込みはOSに任せる
+    Mapped,
+    //画像が必要になる度にファイルから読む。メモリに載らない大きなデータセット向け
+    Stream,
+}
+
+enum ImageStorage {
+    Memory(Vec<u8>),
+    Mapped(Mmap),
+    Stream(RefCell<File>),
+}
+
+//IDX形式の画像とラベルのファイルの組。画像はファイルと同じ1画素1バイトで持ち、
+//取り出す時に初めて転置・0~1の値への変換・前処理をする
+pub struct IdxDataset {
+    images: ImageStorage,
+    labels: Vec<u8>,
+    num_classes: usize,
+    pub preprocessing: Preprocessing,
+}
+
+impl IdxDataset {
+    pub fn open(image_path: &Path, label_path: &Path, mode: LoadMode) -> io::Result<IdxDataset> {
+        let mut image_file = File::open(image_path)?;
+        let mut header = [0u8; IMAGE_HEADER_BYTES as usize];
+        image_file.read_exact(&mut header)?;
+        let num_images = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let rows = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+        let columns = u32::from_be_bytes(header[12..].try_into().unwrap()) as usize;
+        if (rows, columns) != (IMAGE_SIZE, IMAGE_SIZE) {
+            return Err(invalid_data(format!(
+                "画像の大きさが{}x{}です。28x28の画像しか扱えません。",
+                rows, columns
+            )));
+        }
+        let expected_length = IMAGE_HEADER_BYTES + (num_images * IMAGE_BYTES) as u64;
+        if image_file.metadata()?.len() < expected_length {
+            return Err(invalid_data(format!(
+                "画像のファイルが{}枚分の長さより短いです。",
+                num_images
+            )));
+        }
+
+        let mut label_file = File::open(label_path)?;
+        let num_labels = binary_load::get_num_of_labels(&mut label_file)?;
+        if num_labels != num_images {
+            return Err(invalid_data(format!(
+                "画像の数({})とラベルの数({})が違います。",
+                num_images, num_labels
+            )));
+        }
+        let mut labels = vec![0u8; num_labels];
+        label_file.read_exact(&mut labels)?;
+
+        let images = match mode {
+            LoadMode::Memory => {
+                let mut bytes = vec![0u8; num_images * IMAGE_BYTES];
+                image_file.read_exact(&mut bytes)?;
+                ImageStorage::Memory(bytes)
+            }
+            //読み込み中にファイルを書き換えられると中身が変わるが、データセットは書き換えない前提とする
+            LoadMode::Mapped => ImageStorage::Mapped(unsafe { Mmap::map(&image_file)? }),
+            LoadMode::Stream => ImageStorage::Stream(RefCell::new(image_file)),
+        };
+        Ok(IdxDataset {
+            images,
+            num_classes: num_classes(&labels),
+            labels,
+            preprocessing: Preprocessing::default(),
+        })
+    }
+}
+
+impl Dataset for IdxDataset {
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    fn image(&self, index: usize) -> io::Result<Vec<f64>> {
+        let image = match &self.images {
+            ImageStorage::Memory(bytes) => {
+                binary_load::normalize_image(&bytes[index * IMAGE_BYTES..][..IMAGE_BYTES])
+            }
+            ImageStorage::Mapped(map) => {
+                let start = IMAGE_HEADER_BYTES as usize + index * IMAGE_BYTES;
+                binary_load::normalize_image(&map[start..][..IMAGE_BYTES])
+            }
+            ImageStorage::Stream(file) => {
+                let mut file = file.borrow_mut();
+                let mut bytes = [0u8; IMAGE_BYTES];
+                file.seek(SeekFrom::Start(
+                    IMAGE_HEADER_BYTES + (index * IMAGE_BYTES) as u64,
+                ))?;
+                file.read_exact(&mut bytes)?;
+                binary_load::normalize_image(&bytes)
+            }
+        };
+        if self.preprocessing.is_enabled() {
+            Ok(self.preprocessing.apply(&image))
+        } else {
+            Ok(image)
+        }
+    }
+
+    fn label(&self, index: usize) -> u8 {
+        self.labels[index]
+    }
+
+    fn shape(&self) -> Shape {
+        IMAGE_SHAPE
+    }
+
+    fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    fn labels(&self) -> Vec<u8> {
+        self.labels.clone()
+    }
+}
+
+//ラベルの名前のサブフォルダに画像を分けて入れたディレクトリ(例: digits/3/a.png)
+//画像は使う度に読み込むので、形式や大きさは3の手動試験と同じく何でもよい
+pub struct ImageFolderDataset {
+    paths: Vec<PathBuf>,
+    labels: Vec<u8>,
+    num_classes: usize,
+    pub preprocessing: Preprocessing,
+}
+
+impl ImageFolderDataset {
+    pub fn open(dir_path: &Path) -> io::Result<ImageFolderDataset> {
+        let mut samples: Vec<(PathBuf, u8)> = Vec::new();
+        for entry in fs::read_dir(dir_path)? {
+            let label_path = entry?.path();
+            if !label_path.is_dir() {
+                continue;
+            }
+            //数字でない名前のフォルダは飛ばす
+            let Some(label) = label_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<u8>().ok())
+            else {
+                continue;
+            };
+            for entry in fs::read_dir(&label_path)? {
+                let path = entry?.path();
+                if path.is_file() {
+                    samples.push((path, label));
+                }
+            }
+        }
+        if samples.is_empty() {
+            return Err(invalid_data(format!(
+                "{}にラベルの名前のフォルダに入った画像が見つかりませんでした。",
+                dir_path.display()
+            )));
+        }
+        samples.sort();
+        let (paths, labels): (Vec<PathBuf>, Vec<u8>) = samples.into_iter().unzip();
+        Ok(ImageFolderDataset {
+            paths,
+            num_classes: num_classes(&labels),
+            labels,
+            preprocessing: Preprocessing::default(),
+        })
+    }
+
+    pub fn path(&self, index: usize) -> &Path {
+        &self.paths[index]
+    }
+}
+
+impl Dataset for ImageFolderDataset {
+    fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    fn image(&self, index: usize) -> io::Result<Vec<f64>> {
+        image_file::load_model_input(&self.paths[index], &self.preprocessing).map_err(|x| {
+            io::Error::new(x.kind(), format!("{}: {}", self.paths[index].display(), x))
+        })
+    }
+
+    fn label(&self, index: usize) -> u8 {
+        self.labels[index]
+    }
+
+    fn shape(&self) -> Shape {
+        IMAGE_SHAPE
+    }
+
+    fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+}
+
+//1列目がラベル、残りの784列が0~255の画素(行毎に左から右)のCSV(KaggleのDigit Recognizerと同じ形式)
+//1行目が数値でなければ見出しとみなして飛ばす。画素は1画素1バイトでメモリに持つ
+pub struct CsvDataset {
+    pixels: Vec<u8>,
+    labels: Vec<u8>,
+    num_classes: usize,
+    pub preprocessing: Preprocessing,
+}
+
+impl CsvDataset {
+    pub fn open(path: &Path) -> io::Result<CsvDataset> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut pixels = Vec::new();
+        let mut labels = Vec::new();
+        for (line_i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let values: Result<Vec<u8>, _> =
+                line.split(',').map(|value| value.trim().parse()).collect();
+            match values {
+                Ok(values) if values.len() == 1 + IMAGE_BYTES => {
+                    labels.push(values[0]);
+                    pixels.extend_from_slice(&values[1..]);
+                }
+                Err(_) if line_i == 0 => continue,
+                _ => {
+                    return Err(invalid_data(format!(
+                        "{}行目はラベルと0~255の画素{}個の並びではありません。",
+                        line_i + 1,
+                        IMAGE_BYTES
+                    )))
+                }
+            }
+        }
+        Ok(CsvDataset {
+            pixels,
+            num_classes: num_classes(&labels),
+            labels,
+            preprocessing: Preprocessing::default(),
+        })
+    }
+}
+
+impl Dataset for CsvDataset {
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    //CSVの画像はEMNISTと違って転置されていないのでそのまま使う
+    fn image(&self, index: usize) -> io::Result<Vec<f64>> {
+        let image: Vec<f64> = self.pixels[index * IMAGE_BYTES..][..IMAGE_BYTES]
+            .iter()
+            .map(|&value| value as f64 / 255f64)
+            .collect();
+        if self.preprocessing.is_enabled() {
+            Ok(self.preprocessing.apply(&image))
+        } else {
+            Ok(image)
+        }
+    }
+
+    fn label(&self, index: usize) -> u8 {
+        self.labels[index]
+    }
+
+    fn shape(&self) -> Shape {
+        IMAGE_SHAPE
+    }
+
+    fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    fn labels(&self) -> Vec<u8> {
+        self.labels.clone()
+    }
+}
+
+//既にモデルに入力する形になっている画像とラベル
+pub struct InMemoryDataset {
+    images: Vec<Vec<f64>>,
+    labels: Vec<u8>,
+    shape: Shape,
+}
+
+impl InMemoryDataset {
+    pub fn new(
+        shape: Shape,
+        images: Vec<Vec<f64>>,
+        labels: Vec<u8>,
+    ) -> Result<InMemoryDataset, String> {
+        if images.len() != labels.len() {
+            return Err(format!(
+                "画像の数({})とラベルの数({})が違います。",
+                images.len(),
+                labels.len()
+            ));
+        }
+        if images.iter().any(|image| image.len() != shape.size()) {
+            return Err(format!("大きさが{}でない画像があります。", shape.size()));
+        }
+        Ok(InMemoryDataset {
+            images,
+            labels,
+            shape,
+        })
+    }
+}
+
+impl Dataset for InMemoryDataset {
+    fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    fn image(&self, index: usize) -> io::Result<Vec<f64>> {
+        Ok(self.images[index].clone())
+    }
+
+    fn label(&self, index: usize) -> u8 {
+        self.labels[index]
+    }
+
+    fn shape(&self) -> Shape {
+        self.shape
+    }
+
+    fn num_classes(&self) -> usize {
+        num_classes(&self.labels)
+    }
+
+    fn labels(&self) -> Vec<u8> {
+        self.labels.clone()
+    }
+}
+
+//複数のデータセットを順に繋げたもの
+pub struct Concat {
+    datasets: Vec<Box<dyn Dataset>>,
+    //各データセットの先頭の番号
+    starts: Vec<usize>,
+}
+
+impl Concat {
+    pub fn new(datasets: Vec<Box<dyn Dataset>>) -> Result<Concat, String> {
+        if let Some(first) = datasets.first() {
+            if datasets
+                .iter()
+                .any(|dataset| dataset.shape() != first.shape())
+            {
+                return Err("画像の形が違うデータセットは繋げられません。".to_string());
+            }
+        }
+        let mut starts = Vec::with_capacity(datasets.len());
+        let mut start = 0;
+        for dataset in &datasets {
+            starts.push(start);
+            start += dataset.len();
+        }
+        Ok(Concat { datasets, starts })
+    }
+
+    //index番目のデータが入っているデータセットとその中での番号
+    fn locate(&self, index: usize) -> (&dyn Dataset, usize) {
+        let dataset_i = self.starts.partition_point(|&start| start <= index) - 1;
+        (
+            self.datasets[dataset_i].as_ref(),
+            index - self.starts[dataset_i],
+        )
+    }
+}
+
+impl Dataset for Concat {
+    fn len(&self) -> usize {
+        self.datasets.iter().map(|dataset| dataset.len()).sum()
+    }
+
+    fn image(&self, index: usize) -> io::Result<Vec<f64>> {
+        let (dataset, index) = self.locate(index);
+        dataset.image(index)
+    }
+
+    fn label(&self, index: usize) -> u8 {
+        let (dataset, index) = self.locate(index);
+        dataset.label(index)
+    }
+
+    fn shape(&self) -> Shape {
+        self.datasets
+            .first()
+            .map_or(IMAGE_SHAPE, |dataset| dataset.shape())
+    }
+
+    fn num_classes(&self) -> usize {
+        self.datasets
+            .iter()
+            .map(|dataset| dataset.num_classes())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+//元のデータセットのうちindicesの番号のデータだけを、その順に並べたもの
+pub struct Subset<'a> {
+    dataset: &'a dyn Dataset,
+    indices: Vec<usize>,
+}
+
+impl<'a> Subset<'a> {
+    pub fn new(dataset: &'a dyn Dataset, indices: Vec<usize>) -> Subset<'a> {
+        Subset { dataset, indices }
+    }
+}
+
+impl Dataset for Subset<'_> {
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn image(&self, index: usize) -> io::Result<Vec<f64>> {
+        self.dataset.image(self.indices[index])
+    }
+
+    fn label(&self, index: usize) -> u8 {
+        self.dataset.label(self.indices[index])
+    }
+
+    fn shape(&self) -> Shape {
+        self.dataset.shape()
+    }
+
+    //一部のクラスが含まれていなくても、クラスの数は元のデータセットと同じにする
+    fn num_classes(&self) -> usize {
+        self.dataset.num_classes()
+    }
+}
+
+//baseの後ろにextraを繋ぎ、全体のうちratioの割合がextraになるようにextraを繰り返して水増ししたもの。
+//少ない追加データを大きなデータセットに一定の割合で混ぜて訓練する為に使う
+pub struct Mix<'a> {
+    base: &'a dyn Dataset,
+    extra: &'a dyn Dataset,
+    len: usize,
+}
+
+impl<'a> Mix<'a> {
+    pub fn new(
+        base: &'a dyn Dataset,
+        extra: &'a dyn Dataset,
+        ratio: f64,
+    ) -> Result<Mix<'a>, String> {
+        if base.shape() != extra.shape() {
+            return Err("画像の形が違うデータセットは混ぜられません。".to_string());
+        }
+        if extra.is_empty() {
+            return Err("混ぜるデータセットが空です。".to_string());
+        }
+        if !(0.0..1.0).contains(&ratio) {
+            return Err("混ぜる割合は0以上1未満にして下さい。".to_string());
+        }
+        let num_extra = (base.len() as f64 * ratio / (1.0 - ratio)).round() as usize;
+        Ok(Mix {
+            base,
+            extra,
+            len: base.len() + num_extra,
+        })
+    }
+
+    fn locate(&self, index: usize) -> (&dyn Dataset, usize) {
+        match index.checked_sub(self.base.len()) {
+            Some(extra_index) => (self.extra, extra_index % self.extra.len()),
+            None => (self.base, index),
+        }
+    }
+}
+
+impl Dataset for Mix<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn image(&self, index: usize) -> io::Result<Vec<f64>> {
+        let (dataset, index) = self.locate(index);
+        dataset.image(index)
+    }
+
+    fn label(&self, index: usize) -> u8 {
+        let (dataset, index) = self.locate(index);
+        dataset.label(index)
+    }
+
+    fn shape(&self) -> Shape {
+        self.base.shape()
+    }
+
+    fn num_classes(&self) -> usize {
+        self.base.num_classes().max(self.extra.num_classes())
+    }
+}
+
+//無作為に混ぜ、validation_rateの割合を検証用に取り分けて(訓練用, 検証用)に分ける
+pub fn split<'a>(
+    dataset: &'a dyn Dataset,
+    validation_rate: f64,
+    rng: &mut impl Rng,
+) -> (Subset<'a>, Subset<'a>) {
+    let mut indices: Vec<usize> = (0..dataset.len()).collect();
+    indices.shuffle(rng);
+    let num_validation = (dataset.len() as f64 * validation_rate) as usize;
+    let train_indices = indices.split_off(num_validation);
+    (
+        Subset::new(dataset, train_indices),
+        Subset::new(dataset, indices),
+    )
+}
@@ -0,0 +1,204 @@
+use crate::contact_sheet;
+use crate::image::GrayImage;
+use crate::light_network::{self, LightLayer};
+use crate::preprocessing::{self, Preprocessing};
+
+//EMNISTの画像は28x28の中央の20x20に文字があり、周り4画素は余白
+const IMAGE_SIZE: usize = 28;
+const MARGIN_RATE: f64 = 4.0 / 28.0;
+//小さい方の枠のこの割合以上が重なれば、IoUが小さくても同じ数字を囲んだ枠とみなす
+const CONTAINMENT_RATE: f64 = 0.8;
+
+pub struct DetectionSettings {
+    //一番小さい窓の一辺(画素)
+    pub min_window_size: usize,
+    //窓を大きくしていく倍率
+    pub scale_factor: f64,
+    //窓をずらす幅の窓の大きさに対する割合
+    pub stride_rate: f64,
+    //この確率以上の窓だけを数字とみなす
+    pub score_threshold: f64,
+    //これより重なる(IoU)枠は確率の低い方を捨てる
+    pub iou_threshold: f64,
+    //窓の中の文字の明るさの合計のうち、余白の部分にある割合の上限
+    //これを超える窓は数字が中央に収まっていない(背景か数字の一部)とみなす
+    pub max_margin_ink_rate: f64,
+    //窓の面積に対する文字の明るさの合計の割合の下限。これ未満は背景とみなす
+    pub min_ink_rate: f64,
+}
+
+impl DetectionSettings {
+    pub fn default_for(image: &GrayImage) -> DetectionSettings {
+        DetectionSettings {
+            min_window_size: IMAGE_SIZE.min(image.width.min(image.height)),
+            scale_factor: 1.25,
+            stride_rate: 0.125,
+            score_threshold: 0.9,
+            iou_threshold: 0.3,
+            max_margin_ink_rate: 0.1,
+            min_ink_rate: 0.03,
+        }
+    }
+}
+
+//見つけた数字の正方形の枠(元の画像の座標)
+#[derive(Clone, Copy)]
+pub struct Detection {
+    pub left: usize,
+    pub top: usize,
+    pub size: usize,
+    pub label: usize,
+    pub score: f64,
+}
+
+impl Detection {
+    fn intersection(&self, other: &Detection) -> f64 {
+        let overlap = |a_start: usize, a_size: usize, b_start: usize, b_size: usize| {
+            ((a_start + a_size).min(b_start + b_size) as f64 - a_start.max(b_start) as f64).max(0.0)
+        };
+        overlap(self.left, self.size, other.left, other.size)
+            * overlap(self.top, self.size, other.top, other.size)
+    }
+
+    //2つの枠の共通部分の面積/和集合の面積
+    pub fn iou(&self, other: &Detection) -> f64 {
+        let intersection = self.intersection(other);
+        let union = (self.size * self.size + other.size * other.size) as f64 - intersection;
+        intersection / union
+    }
+
+    //共通部分の面積/小さい方の枠の面積
+    pub fn containment(&self, other: &Detection) -> f64 {
+        let smaller = self.size.min(other.size);
+        self.intersection(other) / (smaller * smaller) as f64
+    }
+}
+
+//画像を大きさの違う正方形の窓でずらしながら判定し、数字らしい窓を非最大値抑制で絞り込む
+//モデルには背景のクラスが無いので、確率の閾値と窓の中の文字の量・位置で背景を除く
+//窓の中身はEMNISTと同じように切り出して中心を合わせてから判定する
+pub fn detect(
+    layers: &mut [LightLayer],
+    image: &GrayImage,
+    settings: &DetectionSettings,
+) -> Vec<Detection> {
+    let ink = preprocessing::auto_invert(image);
+    let centering = Preprocessing {
+        center: true,
+        ..Preprocessing::default()
+    };
+    let integral = IntegralImage::new(&ink);
+    let max_size = ink.width.min(ink.height);
+    let mut candidates = Vec::new();
+    let mut size = settings.min_window_size.max(1) as f64;
+    while size.round() as usize <= max_size {
+        let window = size.round() as usize;
+        let stride = ((window as f64 * settings.stride_rate).round() as usize).max(1);
+        let margin = (window as f64 * MARGIN_RATE).round() as usize;
+        for top in (0..=ink.height - window).step_by(stride) {
+            for left in (0..=ink.width - window).step_by(stride) {
+                let total = integral.sum(left, top, window, window);
+                if total < settings.min_ink_rate * (window * window) as f64 {
+                    continue;
+                }
+                let inner = window - 2 * margin;
+                let center = integral.sum(left + margin, top + margin, inner, inner);
+                if (total - center) / total > settings.max_margin_ink_rate {
+                    continue;
+                }
+                let input = centering.apply_to_image(&ink.crop(left, top, window, window));
+                light_network::guess_answer(layers, &input.pixels);
+                let probabilities = layers.last().unwrap().get_activations();
+                let (label, &score) = probabilities
+                    .iter()
+                    .enumerate()
+                    .max_by(|a, b| a.1.total_cmp(b.1))
+                    .unwrap();
+                if score >= settings.score_threshold {
+                    candidates.push(Detection {
+                        left,
+                        top,
+                        size: window,
+                        label,
+                        score,
+                    });
+                }
+            }
+        }
+        size *= settings.scale_factor.max(1.01);
+    }
+    non_maximum_suppression(candidates, settings.iou_threshold)
+}
+
+//確率の高い順(同じなら小さい順)に残し、残した枠と大きく重なる枠や
+//残した枠をほぼ含む(含まれる)枠はラベルに関わらず捨てる
+pub fn non_maximum_suppression(
+    mut candidates: Vec<Detection>,
+    iou_threshold: f64,
+) -> Vec<Detection> {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.size.cmp(&b.size)));
+    let mut kept: Vec<Detection> = Vec::new();
+    for candidate in candidates {
+        if kept.iter().all(|detection| {
+            detection.iou(&candidate) <= iou_threshold
+                && detection.containment(&candidate) < CONTAINMENT_RATE
+        }) {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+//白黒を揃えた画像に枠とラベルを書き込む
+pub fn annotate(image: &GrayImage, detections: &[Detection]) -> GrayImage {
+    let mut annotated = preprocessing::auto_invert(image);
+    for detection in detections {
+        let right = detection.left + detection.size - 1;
+        let bottom = detection.top + detection.size - 1;
+        for x in detection.left..=right {
+            annotated.set(x, detection.top, 1.0);
+            annotated.set(x, bottom, 1.0);
+        }
+        for y in detection.top..=bottom {
+            annotated.set(detection.left, y, 1.0);
+            annotated.set(right, y, 1.0);
+        }
+        contact_sheet::draw_text(
+            &mut annotated,
+            detection.left + 2,
+            detection.top + 2,
+            detection.size,
+            &detection.label.to_string(),
+        );
+    }
+    annotated
+}
+
+//長方形の範囲の画素の合計を定数時間で求める
+struct IntegralImage {
+    width: usize,
+    sums: Vec<f64>,
+}
+
+impl IntegralImage {
+    fn new(image: &GrayImage) -> IntegralImage {
+        let width = image.width + 1;
+        let mut sums = vec![0f64; width * (image.height + 1)];
+        for y in 0..image.height {
+            for x in 0..image.width {
+                sums[(y + 1) * width + x + 1] =
+                    image.get(x, y) + sums[y * width + x + 1] + sums[(y + 1) * width + x]
+                        - sums[y * width + x];
+            }
+        }
+        IntegralImage { width, sums }
+    }
+
+    fn sum(&self, left: usize, top: usize, width: usize, height: usize) -> f64 {
+        let (right, bottom) = (left + width, top + height);
+        self.sums[bottom * self.width + right]
+            - self.sums[top * self.width + right]
+            - self.sums[bottom * self.width + left]
+            + self.sums[top * self.width + left]
+    }
+}
@@ -0,0 +1,110 @@
+use crate::loss::Loss;
+use crate::network::{self, Layer};
+
+//誤差逆伝播で求めた勾配と数値微分を全パラメータについて比べた結果
+pub struct GradientCheck {
+    //浅い層から順に各パラメータの(誤差逆伝播の勾配, 中心差分)
+    pub gradients: Vec<(f64, f64)>,
+    //同じ順の(前進差分, 後退差分)
+    pub one_sided: Vec<(f64, f64)>,
+}
+
+impl GradientCheck {
+    pub fn relative_error(analytical: f64, numerical: f64) -> f64 {
+        //両方ほぼ0の時に丸め誤差で大きくならないよう分母に下限を付ける
+        (analytical - numerical).abs() / (analytical.abs() + numerical.abs()).max(1e-6)
+    }
+
+    //ReLUや最大プーリングの折れ目が±epsilonの間にあると中心差分は正しい勾配とも食い違うが、
+    //折れ目の無い側の片側差分とは一致するので、3つの数値微分のうち一番近いものとの誤差にする
+    pub fn error(&self, i: usize) -> f64 {
+        let (analytical, central) = self.gradients[i];
+        let (forward, backward) = self.one_sided[i];
+        [central, forward, backward]
+            .into_iter()
+            .map(|numerical| GradientCheck::relative_error(analytical, numerical))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn max_relative_error(&self) -> f64 {
+        self.worst().map_or(0.0, |(_, error)| error)
+    }
+
+    //誤差が最も大きいパラメータの番号とその誤差
+    pub fn worst(&self) -> Option<(usize, f64)> {
+        (0..self.gradients.len())
+            .map(|i| (i, self.error(i)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
+
+//ドロップアウトは乱数で損失が変わるので確認中だけ切る
+//誤差逆伝播で更新されるバッチ正規化の移動平均・移動分散は最後に元に戻すので、モデルは変わらない
+pub fn check_gradients(
+    layers: &mut [Layer],
+    images: &[Vec<f64>],
+    answers: &[usize],
+    loss: &Loss,
+    epsilon: f64,
+) -> GradientCheck {
+    let dropout_rates: Vec<f64> = layers
+        .iter_mut()
+        .map(|layer| match layer {
+            Layer::Dense(layer) => std::mem::replace(&mut layer.dropout_rate, 0.0),
+            _ => 0.0,
+        })
+        .collect();
+    let mut running_statistics: Vec<(f64, f64)> = Vec::new();
+    network::for_each_running_statistic(layers, |mean, variance| {
+        running_statistics.push((*mean, *variance))
+    });
+
+    network::for_each_parameter(layers, |_, fix| *fix = 0.0);
+    network::backpropagation(layers, images, answers, loss);
+    //溜まるのはサンプル毎の勾配の和なので損失の平均に合わせる
+    let size_batch = images.len() as f64;
+    let mut analytical: Vec<f64> = Vec::new();
+    network::for_each_parameter(layers, |_, fix| {
+        analytical.push(*fix / size_batch);
+        *fix = 0.0;
+    });
+
+    let loss_zero = network::batch_loss(layers, images, answers, loss);
+    let mut gradients = Vec::with_capacity(analytical.len());
+    let mut one_sided = Vec::with_capacity(analytical.len());
+    for (target_i, analytical) in analytical.into_iter().enumerate() {
+        let shift = |layers: &mut [Layer], amount: f64| {
+            let mut parameter_i = 0;
+            network::for_each_parameter(layers, |parameter, _| {
+                if parameter_i == target_i {
+                    *parameter += amount;
+                }
+                parameter_i += 1;
+            });
+        };
+        shift(layers, epsilon);
+        let loss_plus = network::batch_loss(layers, images, answers, loss);
+        shift(layers, -2.0 * epsilon);
+        let loss_minus = network::batch_loss(layers, images, answers, loss);
+        shift(layers, epsilon);
+        gradients.push((analytical, (loss_plus - loss_minus) / (2.0 * epsilon)));
+        one_sided.push((
+            (loss_plus - loss_zero) / epsilon,
+            (loss_zero - loss_minus) / epsilon,
+        ));
+    }
+
+    for (layer, rate) in layers.iter_mut().zip(dropout_rates) {
+        if let Layer::Dense(layer) = layer {
+            layer.dropout_rate = rate;
+        }
+    }
+    let mut running_statistics = running_statistics.into_iter();
+    network::for_each_running_statistic(layers, |mean, variance| {
+        (*mean, *variance) = running_statistics.next().unwrap();
+    });
+    GradientCheck {
+        gradients,
+        one_sided,
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+//イテレーション1回分の記録。lossは正則化の罰則項を含む
+pub struct IterationRecord {
+    pub epoch: usize,
+    pub iteration: usize,
+    pub loss: f64,
+    pub accuracy: f64,
+    pub learning_rate: f64,
+    pub gradient_norm: f64,
+    pub elapsed_seconds: f64,
+}
+
+//エポック1回分の記録。訓練データの値はそのエポック内のイテレーションの平均
+pub struct EpochRecord {
+    pub epoch: usize,
+    pub loss: f64,
+    pub accuracy: f64,
+    pub learning_rate: f64,
+    pub gradient_norm: f64,
+    pub elapsed_seconds: f64,
+    //検証用データを分けていない時はNone
+    pub validation_loss: Option<f64>,
+    pub validation_accuracy: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct TrainingHistory {
+    pub iterations: Vec<IterationRecord>,
+    pub epochs: Vec<EpochRecord>,
+}
+
+impl TrainingHistory {
+    //直近のエポックのイテレーションをまとめてエポックの記録を加える
+    pub fn finish_epoch(
+        &mut self,
+        epoch: usize,
+        validation: Option<(f64, f64)>,
+        elapsed_seconds: f64,
+    ) -> &EpochRecord {
+        let records: Vec<&IterationRecord> = self
+            .iterations
+            .iter()
+            .filter(|record| record.epoch == epoch)
+            .collect();
+        let count = records.len().max(1) as f64;
+        let mean = |value: fn(&IterationRecord) -> f64| {
+            records.iter().map(|record| value(record)).sum::<f64>() / count
+        };
+        let record = EpochRecord {
+            epoch,
+            loss: mean(|record| record.loss),
+            accuracy: mean(|record| record.accuracy),
+            learning_rate: records.last().map_or(0.0, |record| record.learning_rate),
+            gradient_norm: mean(|record| record.gradient_norm),
+            elapsed_seconds,
+            validation_loss: validation.map(|(loss, _)| loss),
+            validation_accuracy: validation.map(|(_, accuracy)| accuracy),
+        };
+        self.epochs.push(record);
+        self.epochs.last().unwrap()
+    }
+
+    //モデルのフォルダにhistory_iterations.csv、history_epochs.csv、history.jsonを書き出す
+    pub fn save(&self, dir_path: &Path) -> io::Result<()> {
+        let mut iterations_csv = String::from(
+            "epoch,iteration,loss,accuracy,learning_rate,gradient_norm,elapsed_seconds\n",
+        );
+        let mut iterations_json: Vec<String> = Vec::with_capacity(self.iterations.len());
+        for record in &self.iterations {
+            iterations_csv += &format!(
+                "{},{},{},{},{},{},{}\n",
+                record.epoch,
+                record.iteration,
+                record.loss,
+                record.accuracy,
+                record.learning_rate,
+                record.gradient_norm,
+                record.elapsed_seconds
+            );
+            iterations_json.push(format!(
+                "{{\"epoch\":{},\"iteration\":{},\"loss\":{},\"accuracy\":{},\"learning_rate\":{},\"gradient_norm\":{},\"elapsed_seconds\":{}}}",
+                record.epoch,
+                record.iteration,
+                json_number(Some(record.loss)),
+                json_number(Some(record.accuracy)),
+                json_number(Some(record.learning_rate)),
+                json_number(Some(record.gradient_norm)),
+                json_number(Some(record.elapsed_seconds))
+            ));
+        }
+
+        let mut epochs_csv = String::from(
+            "epoch,loss,accuracy,learning_rate,gradient_norm,elapsed_seconds,validation_loss,validation_accuracy\n",
+        );
+        let mut epochs_json: Vec<String> = Vec::with_capacity(self.epochs.len());
+        for record in &self.epochs {
+            let optional = |value: Option<f64>| value.map_or(String::new(), |x| x.to_string());
+            epochs_csv += &format!(
+                "{},{},{},{},{},{},{},{}\n",
+                record.epoch,
+                record.loss,
+                record.accuracy,
+                record.learning_rate,
+                record.gradient_norm,
+                record.elapsed_seconds,
+                optional(record.validation_loss),
+                optional(record.validation_accuracy)
+            );
+            epochs_json.push(format!(
+                "{{\"epoch\":{},\"loss\":{},\"accuracy\":{},\"learning_rate\":{},\"gradient_norm\":{},\"elapsed_seconds\":{},\"validation_loss\":{},\"validation_accuracy\":{}}}",
+                record.epoch,
+                json_number(Some(record.loss)),
+                json_number(Some(record.accuracy)),
+                json_number(Some(record.learning_rate)),
+                json_number(Some(record.gradient_norm)),
+                json_number(Some(record.elapsed_seconds)),
+                json_number(record.validation_loss),
+                json_number(record.validation_accuracy)
+            ));
+        }
+
+        fs::write(dir_path.join("history_iterations.csv"), iterations_csv)?;
+        fs::write(dir_path.join("history_epochs.csv"), epochs_csv)?;
+        fs::write(
+            dir_path.join("history.json"),
+            format!(
+                "{{\n\"iterations\":[\n{}\n],\n\"epochs\":[\n{}\n]\n}}\n",
+                iterations_json.join(",\n"),
+                epochs_json.join(",\n")
+            ),
+        )
+    }
+}
+
+//JSONはNaNや無限大を表せないのでnullにする
+fn json_number(value: Option<f64>) -> String {
+    match value {
+        Some(x) if x.is_finite() => x.to_string(),
+        _ => "null".to_string(),
+    }
+}
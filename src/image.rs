@@ -0,0 +1,153 @@
+//白黒画像。画素は0(背景)から1(文字)の明るさで、左上から行毎に並べる
+#[derive(Clone)]
+pub struct GrayImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<f64>,
+}
+
+impl GrayImage {
+    pub fn new(width: usize, height: usize) -> GrayImage {
+        GrayImage {
+            width,
+            height,
+            pixels: vec![0f64; width * height],
+        }
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<f64>) -> GrayImage {
+        assert_eq!(pixels.len(), width * height);
+        GrayImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f64 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: f64) {
+        self.pixels[y * self.width + x] = value;
+    }
+
+    //画像の外は0として4つの画素から補間する
+    pub fn bilinear(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |x: f64, y: f64| {
+            if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+                0.0
+            } else {
+                self.get(x as usize, y as usize)
+            }
+        };
+        pixel(x0, y0) * (1.0 - fx) * (1.0 - fy)
+            + pixel(x0 + 1.0, y0) * fx * (1.0 - fy)
+            + pixel(x0, y0 + 1.0) * (1.0 - fx) * fy
+            + pixel(x0 + 1.0, y0 + 1.0) * fx * fy
+    }
+
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> GrayImage {
+        let mut cropped = GrayImage::new(width, height);
+        for cy in 0..height {
+            for cx in 0..width {
+                cropped.set(cx, cy, self.get(x + cx, y + cy));
+            }
+        }
+        cropped
+    }
+
+    //各画素が覆う元の画像の範囲を面積で重み付けして平均する(縮小してもギザギザになりにくい)
+    pub fn resize(&self, width: usize, height: usize) -> GrayImage {
+        let scale_x = self.width as f64 / width as f64;
+        let scale_y = self.height as f64 / height as f64;
+        let mut resized = GrayImage::new(width, height);
+        for y in 0..height {
+            let (top, bottom) = (y as f64 * scale_y, (y + 1) as f64 * scale_y);
+            for x in 0..width {
+                let (left, right) = (x as f64 * scale_x, (x + 1) as f64 * scale_x);
+                let mut sum = 0f64;
+                let mut area = 0f64;
+                for sy in top.floor() as usize..(bottom.ceil() as usize).min(self.height) {
+                    let coverage_y = (bottom.min(sy as f64 + 1.0) - top.max(sy as f64)).max(0.0);
+                    for sx in left.floor() as usize..(right.ceil() as usize).min(self.width) {
+                        let coverage_x =
+                            (right.min(sx as f64 + 1.0) - left.max(sx as f64)).max(0.0);
+                        sum += self.get(sx, sy) * coverage_x * coverage_y;
+                        area += coverage_x * coverage_y;
+                    }
+                }
+                resized.set(x, y, if area > 0.0 { sum / area } else { 0.0 });
+            }
+        }
+        resized
+    }
+
+    //3x3の範囲の最大値(膨張、線が太くなる)
+    pub fn dilate(&self) -> GrayImage {
+        self.filter_3x3(f64::max)
+    }
+
+    //3x3の範囲の最小値(収縮、線が細くなる)
+    pub fn erode(&self) -> GrayImage {
+        self.filter_3x3(f64::min)
+    }
+
+    fn filter_3x3(&self, select: fn(f64, f64) -> f64) -> GrayImage {
+        let mut filtered = GrayImage::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut value = self.get(x, y);
+                for ny in y.saturating_sub(1)..(y + 2).min(self.height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(self.width) {
+                        value = select(value, self.get(nx, ny));
+                    }
+                }
+                filtered.set(x, y, value);
+            }
+        }
+        filtered
+    }
+
+    //明るさで重み付けした重心。真っ黒ならNone
+    pub fn center_of_mass(&self) -> Option<(f64, f64)> {
+        let mut total = 0f64;
+        let (mut sum_x, mut sum_y) = (0f64, 0f64);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let value = self.get(x, y);
+                total += value;
+                sum_x += value * x as f64;
+                sum_y += value * y as f64;
+            }
+        }
+        if total > 0.0 {
+            Some((sum_x / total, sum_y / total))
+        } else {
+            None
+        }
+    }
+
+    //thresholdより明るい画素を全て含む最小の長方形(x, y, 幅, 高さ)
+    pub fn bounding_box(&self, threshold: f64) -> Option<(usize, usize, usize, usize)> {
+        let (mut left, mut top) = (usize::MAX, usize::MAX);
+        let (mut right, mut bottom) = (0, 0);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.get(x, y) > threshold {
+                    left = left.min(x);
+                    top = top.min(y);
+                    right = right.max(x);
+                    bottom = bottom.max(y);
+                }
+            }
+        }
+        if left == usize::MAX {
+            None
+        } else {
+            Some((left, top, right - left + 1, bottom - top + 1))
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::binary_load;
+use crate::image::GrayImage;
+use crate::preprocessing::Preprocessing;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+    //PGM(P2, P5)とPPM(P3, P6)
+    Pnm,
+    Bmp,
+    Png,
+}
+
+impl ImageFormat {
+    //拡張子ではなくファイルの先頭のバイト列で判定する
+    pub fn detect(bytes: &[u8]) -> Option<ImageFormat> {
+        match bytes {
+            [0x89, b'P', b'N', b'G', ..] => Some(ImageFormat::Png),
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            [b'P', b'2' | b'3' | b'5' | b'6', ..] => Some(ImageFormat::Pnm),
+            _ => None,
+        }
+    }
+}
+
+//読み込む画像の画素数の上限。壊れたヘッダで巨大な領域を確保しないようにする
+const MAX_PIXELS: usize = 1 << 26;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+//幅x高さxチャンネル数。画素数が上限を超えればエラー
+fn checked_size(width: usize, height: usize, channels: usize) -> io::Result<usize> {
+    width
+        .checked_mul(height)
+        .filter(|&num_pixels| num_pixels <= MAX_PIXELS)
+        .and_then(|num_pixels| num_pixels.checked_mul(channels))
+        .ok_or_else(|| invalid_data("画像が大きすぎます。"))
+}
+
+//画像ファイルを読み込み、白黒画像にする。明るさは元の画像のまま(白が1)
+pub fn load_image(path: &Path) -> io::Result<GrayImage> {
+    let bytes = fs::read(path)?;
+    match ImageFormat::detect(&bytes) {
+        Some(format) => decode_image(&bytes, format),
+        None => Err(invalid_data("対応していない画像の形式です。")),
+    }
+}
+
+pub fn decode_image(bytes: &[u8], format: ImageFormat) -> io::Result<GrayImage> {
+    match format {
+        ImageFormat::Pnm => decode_pnm(bytes),
+        ImageFormat::Bmp => decode_bmp(bytes),
+        ImageFormat::Png => decode_png(bytes),
+    }
+}
+
+//ITU-R BT.601の係数で明るさにする
+fn luminance(r: f64, g: f64, b: f64) -> f64 {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+fn decode_pnm(bytes: &[u8]) -> io::Result<GrayImage> {
+    let magic = *bytes
+        .get(1)
+        .ok_or_else(|| invalid_data("PGM/PPMのヘッダが短すぎます。"))?;
+    if ![b'2', b'3', b'5', b'6'].contains(&magic) {
+        return Err(invalid_data("PGM/PPMの種類が不正です。"));
+    }
+    let mut position = 2;
+    //ヘッダは空白で区切った幅、高さ、最大値。#から行末まではコメント
+    let mut header = [0usize; 3];
+    for value in &mut header {
+        *value = read_pnm_number(bytes, &mut position)?;
+    }
+    let [width, height, max_value] = header;
+    if width == 0 || height == 0 || max_value == 0 || max_value > 65535 {
+        return Err(invalid_data("PGM/PPMのヘッダが不正です。"));
+    }
+    let channels = if magic == b'2' || magic == b'5' { 1 } else { 3 };
+    let num_samples = checked_size(width, height, channels)?;
+    let samples: Vec<usize> = if magic == b'2' || magic == b'3' {
+        //テキスト形式は1つの値に少なくとも1バイト要る
+        if bytes.len().saturating_sub(position) < num_samples {
+            return Err(invalid_data("PGM/PPMの画素が足りません。"));
+        }
+        (0..num_samples)
+            .map(|_| read_pnm_number(bytes, &mut position))
+            .collect::<io::Result<_>>()?
+    } else {
+        //バイナリ形式はヘッダの後の空白1文字に続いて画素が並ぶ
+        position += 1;
+        let sample_size = if max_value < 256 { 1 } else { 2 };
+        let data = bytes
+            .get(position..)
+            .and_then(|rest| rest.get(..num_samples * sample_size))
+            .ok_or_else(|| invalid_data("PGM/PPMの画素が足りません。"))?;
+        data.chunks(sample_size)
+            .map(|sample| {
+                sample
+                    .iter()
+                    .fold(0usize, |value, &byte| value * 256 + byte as usize)
+            })
+            .collect()
+    };
+    let max_value = max_value as f64;
+    let pixels = samples
+        .chunks(channels)
+        .map(|pixel| match pixel {
+            [gray] => *gray as f64 / max_value,
+            [r, g, b] => luminance(*r as f64, *g as f64, *b as f64) / max_value,
+            _ => unreachable!(),
+        })
+        .collect();
+    Ok(GrayImage::from_pixels(width, height, pixels))
+}
+
+fn read_pnm_number(bytes: &[u8], position: &mut usize) -> io::Result<usize> {
+    loop {
+        match bytes.get(*position) {
+            Some(b'#') => {
+                while bytes.get(*position).is_some_and(|&byte| byte != b'\n') {
+                    *position += 1;
+                }
+            }
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            _ => break,
+        }
+    }
+    let start = *position;
+    while bytes.get(*position).is_some_and(u8::is_ascii_digit) {
+        *position += 1;
+    }
+    std::str::from_utf8(&bytes[start..*position])
+        .ok()
+        .and_then(|number| number.parse().ok())
+        .ok_or_else(|| invalid_data("PGM/PPMの数値を読み取れません。"))
+}
+
+//無圧縮のBMP(1, 4, 8ビットのパレット形式と24, 32ビットのBGR形式)
+fn decode_bmp(bytes: &[u8]) -> io::Result<GrayImage> {
+    let read_u32 = |offset: usize| -> io::Result<u32> {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .ok_or_else(|| invalid_data("BMPのヘッダが短すぎます。"))
+    };
+    let read_u16 = |offset: usize| -> io::Result<u16> {
+        bytes
+            .get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+            .ok_or_else(|| invalid_data("BMPのヘッダが短すぎます。"))
+    };
+    let data_offset = read_u32(10)? as usize;
+    let header_size = read_u32(14)? as usize;
+    if header_size < 40 {
+        return Err(invalid_data("古い形式のBMPには対応していません。"));
+    }
+    let width = read_u32(18)? as i32;
+    let height = read_u32(22)? as i32;
+    let bits_per_pixel = read_u16(28)? as usize;
+    let compression = read_u32(30)?;
+    //32ビットのBITFIELDSは標準的なBGRAの並びとみなす
+    if compression != 0 && !(compression == 3 && bits_per_pixel == 32) {
+        return Err(invalid_data("圧縮されたBMPには対応していません。"));
+    }
+    if width <= 0 || height == 0 {
+        return Err(invalid_data("BMPの大きさが不正です。"));
+    }
+    if ![1, 4, 8, 24, 32].contains(&bits_per_pixel) {
+        return Err(invalid_data(
+            "1, 4, 8, 24, 32ビット以外のBMPには対応していません。",
+        ));
+    }
+    //高さが負なら上の行から、正なら下の行から並んでいる
+    let is_top_down = height < 0;
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+    checked_size(width, height, 1)?;
+    //各行は4バイト境界まで詰め物がある
+    let row_size = (bits_per_pixel * width).div_ceil(32) * 4;
+    //画像を確保する前に画素が全て揃っているか確かめる
+    if data_offset
+        .checked_add(row_size * height)
+        .is_none_or(|end| end > bytes.len())
+    {
+        return Err(invalid_data("BMPの画素が足りません。"));
+    }
+    let palette: Vec<f64> = if bits_per_pixel <= 8 {
+        let num_colors = match read_u32(46)? as usize {
+            0 => 1 << bits_per_pixel,
+            x if x <= 1 << bits_per_pixel => x,
+            _ => return Err(invalid_data("BMPのパレットの色数が不正です。")),
+        };
+        let start = 14 + header_size;
+        bytes
+            .get(start..)
+            .and_then(|rest| rest.get(..num_colors * 4))
+            .ok_or_else(|| invalid_data("BMPのパレットが足りません。"))?
+            .chunks(4)
+            .map(|bgr| luminance(bgr[2] as f64, bgr[1] as f64, bgr[0] as f64) / 255.0)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut image = GrayImage::new(width, height);
+    for row in 0..height {
+        let start = data_offset + row * row_size;
+        let data = &bytes[start..start + row_size];
+        let y = if is_top_down { row } else { height - 1 - row };
+        for x in 0..width {
+            let value = match bits_per_pixel {
+                1 | 4 | 8 => {
+                    let bit = x * bits_per_pixel;
+                    let byte = data[bit / 8];
+                    let shift = 8 - bits_per_pixel - bit % 8;
+                    let index = (byte >> shift) as usize & ((1 << bits_per_pixel) - 1);
+                    *palette
+                        .get(index)
+                        .ok_or_else(|| invalid_data("BMPのパレットの番号が不正です。"))?
+                }
+                24 | 32 => {
+                    let bgr = &data[x * bits_per_pixel / 8..];
+                    luminance(bgr[2] as f64, bgr[1] as f64, bgr[0] as f64) / 255.0
+                }
+                _ => unreachable!(),
+            };
+            image.set(x, y, value);
+        }
+    }
+    Ok(image)
+}
+
+//パレットは展開し、16ビットは8ビットに落とす。透明な部分は白い紙の上にあるものとして合成する
+fn decode_png(bytes: &[u8]) -> io::Result<GrayImage> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|x| invalid_data(&format!("PNGを読み込めません: {}", x)))?;
+    let mut buffer = vec![0u8; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|x| invalid_data(&format!("PNGを読み込めません: {}", x)))?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    let pixels = (0..height)
+        .flat_map(|y| {
+            let row = &buffer[y * info.line_size..];
+            (0..width).map(move |x| &row[x * channels..(x + 1) * channels])
+        })
+        .map(|pixel| {
+            let (gray, alpha) = match *pixel {
+                [gray] => (gray as f64, 255.0),
+                [gray, alpha] => (gray as f64, alpha as f64),
+                [r, g, b] => (luminance(r as f64, g as f64, b as f64), 255.0),
+                [r, g, b, alpha] => (luminance(r as f64, g as f64, b as f64), alpha as f64),
+                _ => unreachable!(),
+            };
+            let alpha = alpha / 255.0;
+            (gray / 255.0) * alpha + (1.0 - alpha)
+        })
+        .collect();
+    Ok(GrayImage::from_pixels(width, height, pixels))
+}
+
+//画像ファイルか、character_inputで作った.binファイルを読み込んでモデルに入力する28x28の画像にする
+pub fn load_model_input(path: &Path, preprocessing: &Preprocessing) -> io::Result<Vec<f64>> {
+    let bytes = fs::read(path)?;
+    match ImageFormat::detect(&bytes) {
+        Some(format) => Ok(preprocessing
+            .apply_to_image(&decode_image(&bytes, format)?)
+            .pixels),
+        None => {
+            let image = binary_load::get_next_image(&mut File::open(path)?)?;
+            Ok(preprocessing.apply(&image))
+        }
+    }
+}
+
+//拡張子がpngならPNG、それ以外はバイナリ形式のPGM(P5)で8ビットの白黒画像として保存する
+pub fn save_image(path: &Path, image: &GrayImage) -> io::Result<()> {
+    let data: Vec<u8> = image
+        .pixels
+        .iter()
+        .map(|&value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect();
+    let is_png = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    if is_png {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, image.width as u32, image.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(io::Error::other)
+    } else {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P5\n{} {}\n255\n", image.width, image.height)?;
+        file.write_all(&data)?;
+        file.flush()
+    }
+}
@@ -0,0 +1,416 @@
+use crate::convolution::IMAGE_SHAPE;
+use crate::dataset::Dataset;
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+const FILE_NAME: &str = "normalization.bin";
+//いつも同じ値の画素(EMNISTの縁など)で割り算が発散しないようにする標準偏差の下限
+const MIN_STD: f64 = 0.05;
+//ZCA白色化で固有値に足す値。小さいほど雑音まで強調される
+const ZCA_EPSILON: f64 = 0.1;
+//ZCA白色化の共分散行列を求めるのに使う画像の数の上限(等間隔に選ぶ)
+const ZCA_MAX_SAMPLES: usize = 5000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NormalizationKind {
+    UnitRange,
+    Symmetric,
+    Standardize,
+    PerPixel,
+    Zca,
+}
+
+impl NormalizationKind {
+    pub const ALL: [NormalizationKind; 5] = [
+        NormalizationKind::UnitRange,
+        NormalizationKind::Symmetric,
+        NormalizationKind::Standardize,
+        NormalizationKind::PerPixel,
+        NormalizationKind::Zca,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NormalizationKind::UnitRange => "0~1",
+            NormalizationKind::Symmetric => "-1~1",
+            NormalizationKind::Standardize => "データセット全体の平均と標準偏差で標準化",
+            NormalizationKind::PerPixel => "画素毎の平均と標準偏差で標準化",
+            NormalizationKind::Zca => "ZCA白色化",
+        }
+    }
+}
+
+//モデルに入力する前の画素の変換。統計量は訓練用データから求め、モデルと一緒に保存する
+#[derive(Clone, Default)]
+pub enum InputNormalization {
+    //読み込んだ0~1の値のまま
+    #[default]
+    UnitRange,
+    Symmetric,
+    Standardize {
+        mean: f64,
+        std: f64,
+    },
+    PerPixel {
+        means: Vec<f64>,
+        stds: Vec<f64>,
+    },
+    //画素毎の平均を引いてから、共分散行列の-1/2乗を掛ける
+    Zca {
+        means: Vec<f64>,
+        whitening: Vec<f64>,
+    },
+}
+
+impl InputNormalization {
+    pub fn fit(kind: NormalizationKind, dataset: &dyn Dataset) -> io::Result<InputNormalization> {
+        let size = IMAGE_SHAPE.size();
+        let count = dataset.len().max(1) as f64;
+        let pixel_statistics = || -> io::Result<(Vec<f64>, Vec<f64>)> {
+            let mut sums = vec![0f64; size];
+            let mut square_sums = vec![0f64; size];
+            for index in 0..dataset.len() {
+                for (i, value) in dataset.image(index)?.into_iter().enumerate() {
+                    sums[i] += value;
+                    square_sums[i] += value * value;
+                }
+            }
+            let means: Vec<f64> = sums.iter().map(|sum| sum / count).collect();
+            let variances = square_sums
+                .iter()
+                .zip(&means)
+                .map(|(square_sum, mean)| (square_sum / count - mean * mean).max(0.0))
+                .collect();
+            Ok((means, variances))
+        };
+        Ok(match kind {
+            NormalizationKind::UnitRange => InputNormalization::UnitRange,
+            NormalizationKind::Symmetric => InputNormalization::Symmetric,
+            NormalizationKind::Standardize => {
+                let (means, variances) = pixel_statistics()?;
+                let mean = means.iter().sum::<f64>() / size as f64;
+                //全画素の分散 = 画素毎の分散の平均 + 画素毎の平均の分散
+                let variance = variances.iter().sum::<f64>() / size as f64
+                    + means.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / size as f64;
+                InputNormalization::Standardize {
+                    mean,
+                    std: variance.sqrt().max(MIN_STD),
+                }
+            }
+            NormalizationKind::PerPixel => {
+                let (means, variances) = pixel_statistics()?;
+                InputNormalization::PerPixel {
+                    means,
+                    stds: variances.iter().map(|v| v.sqrt().max(MIN_STD)).collect(),
+                }
+            }
+            NormalizationKind::Zca => {
+                let (means, _) = pixel_statistics()?;
+                let num_samples = dataset.len().min(ZCA_MAX_SAMPLES);
+                let mut covariance = vec![0f64; size * size];
+                for sample_i in 0..num_samples {
+                    let image = dataset.image(sample_i * dataset.len() / num_samples)?;
+                    let centered: Vec<f64> =
+                        image.iter().zip(&means).map(|(x, mean)| x - mean).collect();
+                    for i in 0..size {
+                        if centered[i] == 0.0 {
+                            continue;
+                        }
+                        let row = &mut covariance[i * size..(i + 1) * size];
+                        for j in i..size {
+                            row[j] += centered[i] * centered[j];
+                        }
+                    }
+                }
+                for i in 0..size {
+                    for j in i..size {
+                        covariance[i * size + j] /= num_samples.max(1) as f64;
+                        covariance[j * size + i] = covariance[i * size + j];
+                    }
+                }
+                InputNormalization::Zca {
+                    means,
+                    whitening: inverse_square_root(&covariance, size, ZCA_EPSILON),
+                }
+            }
+        })
+    }
+
+    pub fn kind(&self) -> NormalizationKind {
+        match self {
+            InputNormalization::UnitRange => NormalizationKind::UnitRange,
+            InputNormalization::Symmetric => NormalizationKind::Symmetric,
+            InputNormalization::Standardize { .. } => NormalizationKind::Standardize,
+            InputNormalization::PerPixel { .. } => NormalizationKind::PerPixel,
+            InputNormalization::Zca { .. } => NormalizationKind::Zca,
+        }
+    }
+
+    pub fn apply(&self, image: &[f64]) -> Vec<f64> {
+        match self {
+            InputNormalization::UnitRange => image.to_vec(),
+            InputNormalization::Symmetric => image.iter().map(|x| 2.0 * x - 1.0).collect(),
+            InputNormalization::Standardize { mean, std } => {
+                image.iter().map(|x| (x - mean) / std).collect()
+            }
+            InputNormalization::PerPixel { means, stds } => image
+                .iter()
+                .zip(means.iter().zip(stds))
+                .map(|(x, (mean, std))| (x - mean) / std)
+                .collect(),
+            InputNormalization::Zca { means, whitening } => {
+                let centered: Vec<f64> =
+                    image.iter().zip(means).map(|(x, mean)| x - mean).collect();
+                whitening
+                    .chunks_exact(centered.len())
+                    .map(|row| row.iter().zip(&centered).map(|(w, x)| w * x).sum())
+                    .collect()
+            }
+        }
+    }
+
+    //書式: 種類の番号,統計量(平均,標準偏差,行列の順)
+    pub fn save(&self, dir_path: &Path) -> io::Result<()> {
+        let kind_id = NormalizationKind::ALL
+            .iter()
+            .position(|&kind| kind == self.kind())
+            .unwrap();
+        let values: Vec<f64> = match self {
+            InputNormalization::UnitRange | InputNormalization::Symmetric => Vec::new(),
+            InputNormalization::Standardize { mean, std } => vec![*mean, *std],
+            InputNormalization::PerPixel { means, stds } => [means.as_slice(), stds].concat(),
+            InputNormalization::Zca { means, whitening } => [means.as_slice(), whitening].concat(),
+        };
+        let mut bytes = Vec::with_capacity(8 * (1 + values.len()));
+        bytes.extend_from_slice(&kind_id.to_be_bytes());
+        for value in values {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        File::create(dir_path.join(FILE_NAME))?.write_all(&bytes)
+    }
+
+    //ファイルが無い古いモデルは0~1のまま
+    pub fn load(dir_path: &Path) -> io::Result<InputNormalization> {
+        let path = dir_path.join(FILE_NAME);
+        if !path.exists() {
+            return Ok(InputNormalization::UnitRange);
+        }
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let invalid = || Error::new(ErrorKind::InvalidData, "正規化のファイルが不正です。");
+        if bytes.len() < 8 || bytes.len() % 8 != 0 {
+            return Err(invalid());
+        }
+        let kind_id = usize::from_be_bytes(bytes[..8].try_into().unwrap());
+        let values: Vec<f64> = bytes[8..]
+            .chunks_exact(8)
+            .map(|data| f64::from_be_bytes(data.try_into().unwrap()))
+            .collect();
+        let size = IMAGE_SHAPE.size();
+        let kind = *NormalizationKind::ALL.get(kind_id).ok_or_else(invalid)?;
+        let expected_len = match kind {
+            NormalizationKind::UnitRange | NormalizationKind::Symmetric => 0,
+            NormalizationKind::Standardize => 2,
+            NormalizationKind::PerPixel => 2 * size,
+            NormalizationKind::Zca => size + size * size,
+        };
+        if values.len() != expected_len {
+            return Err(invalid());
+        }
+        Ok(match kind {
+            NormalizationKind::UnitRange => InputNormalization::UnitRange,
+            NormalizationKind::Symmetric => InputNormalization::Symmetric,
+            NormalizationKind::Standardize => InputNormalization::Standardize {
+                mean: values[0],
+                std: values[1],
+            },
+            NormalizationKind::PerPixel => InputNormalization::PerPixel {
+                means: values[..size].to_vec(),
+                stds: values[size..].to_vec(),
+            },
+            NormalizationKind::Zca => InputNormalization::Zca {
+                means: values[..size].to_vec(),
+                whitening: values[size..].to_vec(),
+            },
+        })
+    }
+}
+
+//対称行列matrix(n x n)について (matrix + epsilon I)^(-1/2) = V diag(1/sqrt(λ+epsilon)) V^T を求める
+fn inverse_square_root(matrix: &[f64], n: usize, epsilon: f64) -> Vec<f64> {
+    let (eigenvalues, eigenvectors) = symmetric_eigen(matrix, n);
+    let scales: Vec<f64> = eigenvalues
+        .iter()
+        .map(|&value| 1.0 / (value.max(0.0) + epsilon).sqrt())
+        .collect();
+    let mut result = vec![0f64; n * n];
+    for i in 0..n {
+        let row_i = &eigenvectors[i * n..(i + 1) * n];
+        for j in i..n {
+            let row_j = &eigenvectors[j * n..(j + 1) * n];
+            let value: f64 = (0..n).map(|k| row_i[k] * scales[k] * row_j[k]).sum();
+            result[i * n + j] = value;
+            result[j * n + i] = value;
+        }
+    }
+    result
+}
+
+//対称行列の固有値と固有ベクトル(行列の各列)を、ハウスホルダー変換で三重対角化してから陰的QL法で求める
+//JAMAのEigenvalueDecomposition(tred2, tql2)と同じ手順
+fn symmetric_eigen(matrix: &[f64], n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut v = matrix.to_vec();
+    let mut d = vec![0f64; n];
+    let mut e = vec![0f64; n];
+    if n == 0 {
+        return (d, v);
+    }
+    let at = |i: usize, j: usize| i * n + j;
+
+    //三重対角化
+    for j in 0..n {
+        d[j] = v[at(n - 1, j)];
+    }
+    for i in (1..n).rev() {
+        let scale: f64 = d[..i].iter().map(|x| x.abs()).sum();
+        let mut h = 0f64;
+        if scale == 0.0 {
+            e[i] = d[i - 1];
+            for j in 0..i {
+                d[j] = v[at(i - 1, j)];
+                v[at(i, j)] = 0.0;
+                v[at(j, i)] = 0.0;
+            }
+        } else {
+            for x in &mut d[..i] {
+                *x /= scale;
+                h += *x * *x;
+            }
+            let mut f = d[i - 1];
+            let mut g = if f > 0.0 { -h.sqrt() } else { h.sqrt() };
+            e[i] = scale * g;
+            h -= f * g;
+            d[i - 1] = f - g;
+            e[..i].fill(0.0);
+            for j in 0..i {
+                f = d[j];
+                v[at(j, i)] = f;
+                g = e[j] + v[at(j, j)] * f;
+                for k in j + 1..i {
+                    g += v[at(k, j)] * d[k];
+                    e[k] += v[at(k, j)] * f;
+                }
+                e[j] = g;
+            }
+            f = 0.0;
+            for j in 0..i {
+                e[j] /= h;
+                f += e[j] * d[j];
+            }
+            let hh = f / (h + h);
+            for j in 0..i {
+                e[j] -= hh * d[j];
+            }
+            for j in 0..i {
+                f = d[j];
+                g = e[j];
+                for k in j..i {
+                    v[at(k, j)] -= f * e[k] + g * d[k];
+                }
+                d[j] = v[at(i - 1, j)];
+                v[at(i, j)] = 0.0;
+            }
+        }
+        d[i] = h;
+    }
+    for i in 0..n - 1 {
+        v[at(n - 1, i)] = v[at(i, i)];
+        v[at(i, i)] = 1.0;
+        let h = d[i + 1];
+        if h != 0.0 {
+            for k in 0..=i {
+                d[k] = v[at(k, i + 1)] / h;
+            }
+            for j in 0..=i {
+                let g: f64 = (0..=i).map(|k| v[at(k, i + 1)] * v[at(k, j)]).sum();
+                for k in 0..=i {
+                    v[at(k, j)] -= g * d[k];
+                }
+            }
+        }
+        for k in 0..=i {
+            v[at(k, i + 1)] = 0.0;
+        }
+    }
+    for j in 0..n {
+        d[j] = v[at(n - 1, j)];
+        v[at(n - 1, j)] = 0.0;
+    }
+    v[at(n - 1, n - 1)] = 1.0;
+    e[0] = 0.0;
+
+    //陰的QL法
+    for i in 1..n {
+        e[i - 1] = e[i];
+    }
+    e[n - 1] = 0.0;
+    let mut f = 0f64;
+    let mut tst1 = 0f64;
+    for l in 0..n {
+        tst1 = tst1.max(d[l].abs() + e[l].abs());
+        let mut m = l;
+        while m < n - 1 && e[m].abs() > f64::EPSILON * tst1 {
+            m += 1;
+        }
+        if m > l {
+            loop {
+                let mut g = d[l];
+                let mut p = (d[l + 1] - g) / (2.0 * e[l]);
+                let mut r = p.hypot(1.0);
+                if p < 0.0 {
+                    r = -r;
+                }
+                d[l] = e[l] / (p + r);
+                d[l + 1] = e[l] * (p + r);
+                let dl1 = d[l + 1];
+                let mut h = g - d[l];
+                for x in &mut d[l + 2..] {
+                    *x -= h;
+                }
+                f += h;
+                p = d[m];
+                let (mut c, mut c2, mut c3) = (1f64, 1f64, 1f64);
+                let el1 = e[l + 1];
+                let (mut s, mut s2) = (0f64, 0f64);
+                for i in (l..m).rev() {
+                    c3 = c2;
+                    c2 = c;
+                    s2 = s;
+                    g = c * e[i];
+                    h = c * p;
+                    r = p.hypot(e[i]);
+                    e[i + 1] = s * r;
+                    s = e[i] / r;
+                    c = p / r;
+                    p = c * d[i] - s * g;
+                    d[i + 1] = h + s * (c * g + s * d[i]);
+                    for k in 0..n {
+                        h = v[at(k, i + 1)];
+                        v[at(k, i + 1)] = s * v[at(k, i)] + c * h;
+                        v[at(k, i)] = c * v[at(k, i)] - s * h;
+                    }
+                }
+                p = -s * s2 * c3 * el1 * e[l] / dl1;
+                e[l] = s * p;
+                d[l] = c * p;
+                if e[l].abs() <= f64::EPSILON * tst1 {
+                    break;
+                }
+            }
+        }
+        d[l] += f;
+        e[l] = 0.0;
+    }
+    (d, v)
+}
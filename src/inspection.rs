@@ -0,0 +1,268 @@
+use crate::convolution::Shape;
+use crate::dataset::{Dataset, IDX_IMAGE_MAGIC, IDX_LABEL_MAGIC};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::path::Path;
+
+const IMAGE_SIZE: usize = 28;
+//一番明るい画素と一番暗い画素の差がこれ未満の画像は何も書かれていないとみなす
+const BLANK_RANGE: f64 = 0.1;
+//番号の一覧を表示する時の最大の件数
+const MAX_LISTED: usize = 10;
+
+//IDX形式の画像とラベルのファイルのヘッダと長さを調べ、見つかった問題を返す
+pub fn check_idx_files(image_path: &Path, label_path: &Path) -> io::Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let image_header = read_header(image_path, 16)?;
+    let label_header = read_header(label_path, 8)?;
+    let field = |header: &[u8], i: usize| u32::from_be_bytes(header[i..i + 4].try_into().unwrap());
+    let mut num_images = None;
+    match &image_header {
+        Some((header, length)) => {
+            if field(header, 0) != IDX_IMAGE_MAGIC {
+                problems.push(format!(
+                    "画像のファイルの識別子が{:#010x}です({:#010x}のはずです)。",
+                    field(header, 0),
+                    IDX_IMAGE_MAGIC
+                ));
+            }
+            let (count, rows, columns) = (
+                field(header, 4) as u64,
+                field(header, 8) as u64,
+                field(header, 12) as u64,
+            );
+            if (rows, columns) != (IMAGE_SIZE as u64, IMAGE_SIZE as u64) {
+                problems.push(format!(
+                    "画像の大きさが{}x{}です(28x28のはずです)。",
+                    rows, columns
+                ));
+            }
+            let expected = 16 + count * rows * columns;
+            if *length != expected {
+                problems.push(format!(
+                    "画像のファイルの長さが{}バイトです。ヘッダの{}枚分なら{}バイトのはずです。",
+                    length, count, expected
+                ));
+            }
+            num_images = Some(count);
+        }
+        None => problems.push("画像のファイルがヘッダ(16バイト)より短いです。".to_string()),
+    }
+    match &label_header {
+        Some((header, length)) => {
+            if field(header, 0) != IDX_LABEL_MAGIC {
+                problems.push(format!(
+                    "ラベルのファイルの識別子が{:#010x}です({:#010x}のはずです)。",
+                    field(header, 0),
+                    IDX_LABEL_MAGIC
+                ));
+            }
+            let count = field(header, 4) as u64;
+            if *length != 8 + count {
+                problems.push(format!(
+                    "ラベルのファイルの長さが{}バイトです。ヘッダの{}個分なら{}バイトのはずです。",
+                    length,
+                    count,
+                    8 + count
+                ));
+            }
+            if let Some(num_images) = num_images.filter(|&num_images| num_images != count) {
+                problems.push(format!(
+                    "画像の数({})とラベルの数({})が違います。",
+                    num_images, count
+                ));
+            }
+        }
+        None => problems.push("ラベルのファイルがヘッダ(8バイト)より短いです。".to_string()),
+    }
+    Ok(problems)
+}
+
+//ファイルの先頭lengthバイトとファイルの長さ。ファイルがそれより短ければNone
+fn read_header(path: &Path, length: usize) -> io::Result<Option<(Vec<u8>, u64)>> {
+    let mut file = File::open(path)?;
+    let file_length = file.metadata()?.len();
+    let mut header = vec![0u8; length];
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(Some((header, file_length))),
+        Err(x) if x.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(x) => Err(x),
+    }
+}
+
+pub struct DatasetReport {
+    pub num_samples: usize,
+    pub shape: Shape,
+    //0~num_classes-1の各ラベルの数
+    pub class_counts: Vec<usize>,
+    //範囲外のラベルを持つデータの(番号, ラベル)
+    pub out_of_range: Vec<(usize, u8)>,
+    pub pixel_mean: f64,
+    pub pixel_std: f64,
+    //全く同じ画像の番号の組(2つ以上)
+    pub duplicates: Vec<Vec<usize>>,
+    //そのうちラベルが食い違っている組の数
+    pub conflicting_duplicates: usize,
+    pub blank: Vec<usize>,
+    //読み込めなかったデータの(番号, エラー)
+    pub unreadable: Vec<(usize, String)>,
+}
+
+//全てのデータを1回ずつ読んで調べる。ラベルは0~num_classes-1を正しい範囲とする
+pub fn inspect(dataset: &dyn Dataset, num_classes: usize) -> DatasetReport {
+    let mut class_counts = vec![0usize; num_classes];
+    let mut out_of_range = Vec::new();
+    let mut sum = 0f64;
+    let mut square_sum = 0f64;
+    let mut num_pixels = 0usize;
+    let mut hashes: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut blank = Vec::new();
+    let mut unreadable = Vec::new();
+    for index in 0..dataset.len() {
+        let label = dataset.label(index);
+        match class_counts.get_mut(label as usize) {
+            Some(count) => *count += 1,
+            None => out_of_range.push((index, label)),
+        }
+        let image = match dataset.image(index) {
+            Ok(image) => image,
+            Err(x) => {
+                unreadable.push((index, x.to_string()));
+                continue;
+            }
+        };
+        let (mut min, mut max) = (f64::MAX, f64::MIN);
+        for &value in &image {
+            sum += value;
+            square_sum += value * value;
+            min = min.min(value);
+            max = max.max(value);
+        }
+        num_pixels += image.len();
+        if max - min < BLANK_RANGE {
+            blank.push(index);
+        }
+        //8ビットに丸めた画素の並びのハッシュで同じ画像を探す
+        let mut hasher = DefaultHasher::new();
+        for &value in &image {
+            ((value.clamp(0.0, 1.0) * 255.0).round() as u8).hash(&mut hasher);
+        }
+        hashes.entry(hasher.finish()).or_default().push(index);
+    }
+    let mut duplicates: Vec<Vec<usize>> = hashes
+        .into_values()
+        .filter(|indices| indices.len() >= 2)
+        .collect();
+    duplicates.sort();
+    let conflicting_duplicates = duplicates
+        .iter()
+        .filter(|indices| {
+            indices
+                .iter()
+                .any(|&i| dataset.label(i) != dataset.label(indices[0]))
+        })
+        .count();
+    let pixel_mean = sum / num_pixels.max(1) as f64;
+    let pixel_variance = (square_sum / num_pixels.max(1) as f64 - pixel_mean * pixel_mean).max(0.0);
+    DatasetReport {
+        num_samples: dataset.len(),
+        shape: dataset.shape(),
+        class_counts,
+        out_of_range,
+        pixel_mean,
+        pixel_std: pixel_variance.sqrt(),
+        duplicates,
+        conflicting_duplicates,
+        blank,
+        unreadable,
+    }
+}
+
+impl DatasetReport {
+    //一番多いクラスの数/一番少ないクラスの数。数が0のクラスがあればNone
+    pub fn imbalance_ratio(&self) -> Option<f64> {
+        let max = *self.class_counts.iter().max()?;
+        let min = *self.class_counts.iter().min()?;
+        (min > 0).then(|| max as f64 / min as f64)
+    }
+
+    //何か問題が見つかったか
+    pub fn has_problems(&self) -> bool {
+        !self.out_of_range.is_empty()
+            || !self.duplicates.is_empty()
+            || !self.blank.is_empty()
+            || !self.unreadable.is_empty()
+            || self.class_counts.contains(&0)
+    }
+
+    pub fn print(&self) {
+        println!("データの数: {}", self.num_samples);
+        println!(
+            "画像の形: {}x{}x{}",
+            self.shape.channels, self.shape.height, self.shape.width
+        );
+        println!("ラベル毎の数:");
+        for (label, &count) in self.class_counts.iter().enumerate() {
+            println!(
+                "  {}: {} ({:.1}%)",
+                label,
+                count,
+                100.0 * count as f64 / self.num_samples.max(1) as f64
+            );
+        }
+        match self.imbalance_ratio() {
+            Some(ratio) => println!("一番多いラベルと一番少ないラベルの数の比: {:.2}", ratio),
+            None => println!("1つも無いラベルがあります。"),
+        }
+        println!(
+            "画素の平均: {:.4}\t標準偏差: {:.4}",
+            self.pixel_mean, self.pixel_std
+        );
+        println!(
+            "範囲外(0~{})のラベル: {}件{}",
+            self.class_counts.len().saturating_sub(1),
+            self.out_of_range.len(),
+            list(
+                self.out_of_range
+                    .iter()
+                    .map(|(i, label)| format!("{}({})", i, label))
+            )
+        );
+        println!(
+            "全く同じ画像の組: {}組(うちラベルが違うもの{}組){}",
+            self.duplicates.len(),
+            self.conflicting_duplicates,
+            list(self.duplicates.iter().map(|indices| {
+                let indices: Vec<String> = indices.iter().map(|i| i.to_string()).collect();
+                format!("[{}]", indices.join(","))
+            }))
+        );
+        println!(
+            "何も書かれていない画像: {}件{}",
+            self.blank.len(),
+            list(self.blank.iter().map(|i| i.to_string()))
+        );
+        println!("読み込めなかった画像: {}件", self.unreadable.len());
+        for (index, error) in self.unreadable.iter().take(MAX_LISTED) {
+            println!("  {}: {}", index, error);
+        }
+    }
+}
+
+//最初のMAX_LISTED件を並べ、残りは件数だけ書く
+fn list(items: impl ExactSizeIterator<Item = String>) -> String {
+    let total = items.len();
+    if total == 0 {
+        return String::new();
+    }
+    let shown: Vec<String> = items.take(MAX_LISTED).collect();
+    let rest = if total > MAX_LISTED {
+        format!(" 他{}件", total - MAX_LISTED)
+    } else {
+        String::new()
+    };
+    format!(" {}{}", shown.join(" "), rest)
+}
@@ -1,4 +1,28 @@
+pub mod architecture;
+pub mod augmentation;
+pub mod batch_predict;
 pub mod binary_load;
 pub mod binary_save;
+pub mod canvas;
+pub mod contact_sheet;
+pub mod conversion;
+pub mod convolution;
+pub mod cross_validation;
+pub mod dashboard;
+pub mod dataset;
+pub mod detection;
+pub mod gradient_check;
+pub mod history;
+pub mod image;
+pub mod image_file;
+pub mod input_normalization;
+pub mod inspection;
 pub mod light_network;
+pub mod loss;
 pub mod network;
+pub mod preprocessing;
+pub mod regularization;
+pub mod search;
+pub mod segmentation;
+pub mod training;
+pub mod user_dataset;
@@ -1,52 +1,191 @@
-use crate::{binary_load::load_neuron, network};
-use std::fs::File;
+use crate::{
+    architecture::{Architecture, LayerSpec},
+    binary_load::{self, load_neuron},
+    convolution::{self, ConvolutionSettings, PoolingSettings, Shape, IMAGE_SHAPE},
+    input_normalization::InputNormalization,
+    network::{self, Activation, Normalization},
+};
+use std::fs::{self, File};
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
 
-pub fn guess_answer(layers: &mut Vec<LightLayer>, image: &Vec<f64>) {
-    let layers_last_i = layers.len() - 1;
+pub fn load_model(model_name: &str) -> io::Result<Vec<LightLayer>> {
+    let model_path = Path::new("save_datas").join(model_name);
+    let architecture = load_architecture(&model_path)?;
+    let invalid = || Error::new(ErrorKind::InvalidData, "モデルの構造が不正です。");
+    let input_shapes = architecture.input_shapes();
+    let mut layers: Vec<LightLayer> = Vec::with_capacity(architecture.layers.len() + 1);
+    //訓練時と同じ入力の正規化を最初の層として掛ける
+    let normalization = InputNormalization::load(&model_path)?;
+    if !matches!(normalization, InputNormalization::UnitRange) {
+        layers.push(LightLayer::Normalization(normalization, Vec::new()));
+    }
+    for (i, (spec, shape)) in architecture.layers.iter().zip(input_shapes).enumerate() {
+        let layer_path = model_path.join(format!("layer{}.bin", i));
+        let output_shape = spec.output_shape(shape).ok_or_else(invalid)?;
+        let layer = match spec {
+            LayerSpec::Dense {
+                size,
+                activation,
+                normalization,
+                ..
+            } => {
+                let mut file = File::open(layer_path)?;
+                let mut layer = LightDenseLayer::new(&mut file, *size, shape.size());
+                layer.activation = *activation;
+                //バッチ正規化は重みとバイアスに畳み込んである
+                let norm_path = model_path.join(format!("layer{}_norm.bin", i));
+                if *normalization == Normalization::Layer || norm_path.exists() {
+                    let mut norm_file = File::open(norm_path)?;
+                    layer.layer_normalization = Some(binary_load::load_layer_normalization(
+                        &mut norm_file,
+                        *size,
+                    )?);
+                }
+                LightLayer::Dense(layer)
+            }
+            LayerSpec::Convolution {
+                settings,
+                activation,
+                ..
+            } => {
+                let mut file = File::open(layer_path)?;
+                let mut layer =
+                    LightConvolutionLayer::new(&mut file, *settings, shape, output_shape)?;
+                layer.activation = *activation;
+                LightLayer::Convolution(layer)
+            }
+            LayerSpec::Pooling(settings) => LightLayer::Pooling(LightPoolingLayer {
+                settings: *settings,
+                input_shape: shape,
+                output_shape,
+                activations: Vec::new(),
+            }),
+            LayerSpec::Flatten => LightLayer::Flatten(Vec::new()),
+        };
+        layers.push(layer);
+    }
+    Ok(layers)
+}
+
+//architecture.txtが無い古いモデルは全結合層だけ
+pub fn load_architecture(model_path: &Path) -> io::Result<Architecture> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let architecture_path = model_path.join("architecture.txt");
+    if architecture_path.exists() {
+        let spec = fs::read_to_string(architecture_path)?;
+        return Architecture::parse(spec.trim(), IMAGE_SHAPE).map_err(invalid);
+    }
+    let mut header_file = File::open(model_path.join("header.bin"))?;
+    let (_, layer_sizes) = binary_load::load_header(&mut header_file)?;
+    let elements: Vec<String> = layer_sizes
+        .iter()
+        .map(|size| format!("dense({})", size))
+        .collect();
+    Architecture::parse(&elements.join("-"), IMAGE_SHAPE).map_err(invalid)
+}
+
+pub fn guess_answer(layers: &mut [LightLayer], image: &[f64]) {
     let input_layer = layers.first_mut().unwrap();
-    input_layer.set_neurons_activations(image, layers_last_i == 0);
+    input_layer.set_activations(image);
     let mut iter_layers = layers.iter_mut();
     let mut shallower_layer = iter_layers.next().unwrap();
-    let mut current_layer_i: usize = 1;
     for current_layer in iter_layers {
-        current_layer.set_neurons_activations(
-            &shallower_layer.get_neurons_activations(),
-            current_layer_i == layers_last_i,
-        );
+        current_layer.set_activations(&shallower_layer.get_activations());
         shallower_layer = current_layer;
-        current_layer_i += 1;
     }
     //SOFTMAX
+    let Some(LightLayer::Dense(output_layer)) = layers.last_mut() else {
+        return;
+    };
     let mut exp_sum = 0f64;
-    for ref mut neuron in &mut layers.last_mut().unwrap().neurons {
+    for ref mut neuron in &mut output_layer.neurons {
         neuron.activation = f64::exp(neuron.activation);
         exp_sum += neuron.activation;
     }
-    for ref mut neuron in &mut layers.last_mut().unwrap().neurons {
+    for ref mut neuron in &mut output_layer.neurons {
         neuron.activation /= exp_sum;
     }
 }
 
-pub struct LightLayer {
-    neurons: Vec<LightNeuron>,
+pub enum LightLayer {
+    Dense(LightDenseLayer),
+    Convolution(LightConvolutionLayer),
+    Pooling(LightPoolingLayer),
+    Flatten(Vec<f64>),
+    //正規化と、正規化した入力
+    Normalization(InputNormalization, Vec<f64>),
 }
 
 impl LightLayer {
-    pub fn new(file: &mut File, size_this_layer: usize, size_shallower_layer: usize) -> LightLayer {
+    pub fn set_activations(&mut self, ref_shallower_activations: &[f64]) {
+        match self {
+            LightLayer::Dense(layer) => layer.set_neurons_activations(ref_shallower_activations),
+            LightLayer::Convolution(layer) => layer.set_activations(ref_shallower_activations),
+            LightLayer::Pooling(layer) => layer.set_activations(ref_shallower_activations),
+            LightLayer::Flatten(activations) => *activations = ref_shallower_activations.to_vec(),
+            LightLayer::Normalization(normalization, activations) => {
+                *activations = normalization.apply(ref_shallower_activations)
+            }
+        }
+    }
+
+    pub fn get_activations(&self) -> Vec<f64> {
+        match self {
+            LightLayer::Dense(layer) => layer.get_neurons_activations(),
+            LightLayer::Convolution(layer) => layer.activations.clone(),
+            LightLayer::Pooling(layer) => layer.activations.clone(),
+            LightLayer::Flatten(activations) | LightLayer::Normalization(_, activations) => {
+                activations.clone()
+            }
+        }
+    }
+}
+
+pub struct LightDenseLayer {
+    neurons: Vec<LightNeuron>,
+    activation: Activation,
+    //各ニューロンのgammaとbeta
+    layer_normalization: Option<Vec<(f64, f64)>>,
+}
+
+impl LightDenseLayer {
+    pub fn new(
+        file: &mut File,
+        size_this_layer: usize,
+        size_shallower_layer: usize,
+    ) -> LightDenseLayer {
         let mut neurons: Vec<LightNeuron> = Vec::with_capacity(size_this_layer);
         for _ in 0..size_this_layer {
             neurons.push(LightNeuron::new(file, size_shallower_layer));
         }
-        LightLayer { neurons }
+        LightDenseLayer {
+            neurons,
+            activation: Activation::LeakyRelu,
+            layer_normalization: None,
+        }
     }
 
-    pub fn set_neurons_activations(
-        &mut self,
-        ref_shallower_activations: &Vec<f64>,
-        is_output_layer: bool,
-    ) {
+    pub fn set_neurons_activations(&mut self, ref_shallower_activations: &[f64]) {
+        for neuron in &mut self.neurons {
+            neuron.set_weighted_sum(ref_shallower_activations);
+        }
+        if let Some(parameters) = &self.layer_normalization {
+            let size = self.neurons.len() as f64;
+            let mean = self.neurons.iter().map(|n| n.activation).sum::<f64>() / size;
+            let variance = self
+                .neurons
+                .iter()
+                .map(|n| (n.activation - mean).powi(2))
+                .sum::<f64>()
+                / size;
+            let std = (variance + network::NORMALIZATION_EPSILON).sqrt();
+            for (neuron, (gamma, beta)) in self.neurons.iter_mut().zip(parameters) {
+                neuron.activation = gamma * (neuron.activation - mean) / std + beta;
+            }
+        }
         for neuron in &mut self.neurons {
-            neuron.set_activation(ref_shallower_activations, is_output_layer);
+            neuron.activation = self.activation.apply(neuron.activation);
         }
     }
 
@@ -77,15 +216,77 @@ impl LightNeuron {
         }
     }
 
-    pub fn set_activation(&mut self, ref_shallower_activations: &Vec<f64>, is_output_layer: bool) {
-        self.activation = 0f64;
+    pub fn set_weighted_sum(&mut self, ref_shallower_activations: &[f64]) {
+        self.activation = self.bias;
         for (weight, shallower_activation) in self.weights.iter().zip(ref_shallower_activations) {
             self.activation += weight * shallower_activation;
         }
-        self.activation = if is_output_layer {
-            self.activation + self.bias
-        } else {
-            network::leaky_relu(self.activation + self.bias)
-        };
+    }
+}
+
+pub struct LightConvolutionLayer {
+    //各フィルタの重みとバイアス
+    filters: Vec<(Vec<f64>, f64)>,
+    settings: ConvolutionSettings,
+    activation: Activation,
+    input_shape: Shape,
+    output_shape: Shape,
+    activations: Vec<f64>,
+}
+
+impl LightConvolutionLayer {
+    pub fn new(
+        file: &mut File,
+        settings: ConvolutionSettings,
+        input_shape: Shape,
+        output_shape: Shape,
+    ) -> io::Result<LightConvolutionLayer> {
+        let size_filter = input_shape.channels * settings.kernel_size * settings.kernel_size;
+        let filters = (0..output_shape.channels)
+            .map(|_| load_neuron(file, size_filter))
+            .collect::<io::Result<_>>()?;
+        Ok(LightConvolutionLayer {
+            filters,
+            settings,
+            activation: Activation::LeakyRelu,
+            input_shape,
+            output_shape,
+            activations: Vec::new(),
+        })
+    }
+
+    fn set_activations(&mut self, ref_shallower_activations: &[f64]) {
+        let filters: Vec<(&[f64], f64)> = self
+            .filters
+            .iter()
+            .map(|(weights, bias)| (&weights[..], *bias))
+            .collect();
+        self.activations = convolution::convolve(
+            ref_shallower_activations,
+            self.input_shape,
+            self.output_shape,
+            self.settings,
+            &filters,
+        )
+        .into_iter()
+        .map(|x| self.activation.apply(x))
+        .collect();
+    }
+}
+
+pub struct LightPoolingLayer {
+    settings: PoolingSettings,
+    input_shape: Shape,
+    output_shape: Shape,
+    activations: Vec<f64>,
+}
+
+impl LightPoolingLayer {
+    fn set_activations(&mut self, ref_shallower_activations: &[f64]) {
+        (self.activations, _) = self.settings.pool(
+            ref_shallower_activations,
+            self.input_shape,
+            self.output_shape,
+        );
     }
 }
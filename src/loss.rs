@@ -0,0 +1,113 @@
+//log(0)を避ける為の下限
+const MIN_PROBABILITY: f64 = 1e-15;
+
+//出力層はSoftmaxを通した確率を前提とする
+#[derive(Clone)]
+pub enum Loss {
+    CrossEntropy { label_smoothing: f64 },
+    WeightedCrossEntropy { class_weights: Vec<f64> },
+    Focal { gamma: f64 },
+    MeanSquaredError,
+}
+
+impl Loss {
+    pub fn name(&self) -> String {
+        match self {
+            Loss::CrossEntropy { label_smoothing } if *label_smoothing > 0.0 => {
+                format!("cross entropy (label smoothing {})", label_smoothing)
+            }
+            Loss::CrossEntropy { .. } => "cross entropy".to_string(),
+            Loss::WeightedCrossEntropy { .. } => "weighted cross entropy".to_string(),
+            Loss::Focal { gamma } => format!("focal (gamma {})", gamma),
+            Loss::MeanSquaredError => "mean squared error".to_string(),
+        }
+    }
+
+    pub fn value(&self, probabilities: &[f64], answer: usize) -> f64 {
+        match self {
+            Loss::CrossEntropy { label_smoothing } => {
+                let targets = smoothed_targets(probabilities.len(), answer, *label_smoothing);
+                -probabilities
+                    .iter()
+                    .zip(&targets)
+                    .map(|(p, t)| t * p.max(MIN_PROBABILITY).ln())
+                    .sum::<f64>()
+            }
+            Loss::WeightedCrossEntropy { class_weights } => {
+                -class_weights[answer] * probabilities[answer].max(MIN_PROBABILITY).ln()
+            }
+            Loss::Focal { gamma } => {
+                let p = probabilities[answer].max(MIN_PROBABILITY);
+                -(1.0 - p).powf(*gamma) * p.ln()
+            }
+            Loss::MeanSquaredError => {
+                let targets = smoothed_targets(probabilities.len(), answer, 0.0);
+                probabilities
+                    .iter()
+                    .zip(&targets)
+                    .map(|(p, t)| (p - t).powi(2))
+                    .sum::<f64>()
+                    / probabilities.len() as f64
+            }
+        }
+    }
+
+    //Softmax前の出力(ロジット)についての損失の勾配
+    pub fn output_deltas(&self, probabilities: &[f64], answer: usize) -> Vec<f64> {
+        match self {
+            Loss::CrossEntropy { label_smoothing } => {
+                let targets = smoothed_targets(probabilities.len(), answer, *label_smoothing);
+                probabilities
+                    .iter()
+                    .zip(&targets)
+                    .map(|(p, t)| p - t)
+                    .collect()
+            }
+            Loss::WeightedCrossEntropy { class_weights } => {
+                let weight = class_weights[answer];
+                probabilities
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| weight * (p - if i == answer { 1.0 } else { 0.0 }))
+                    .collect()
+            }
+            Loss::Focal { gamma } => {
+                //dL/dz_i = dL/dp_y * p_y * (δ_iy - p_i)
+                let p = probabilities[answer].max(MIN_PROBABILITY);
+                let q = (1.0 - p).max(MIN_PROBABILITY);
+                let coefficient = gamma * q.powf(gamma - 1.0) * p * p.ln() - q.powf(*gamma);
+                probabilities
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p_i)| coefficient * (if i == answer { 1.0 } else { 0.0 } - p_i))
+                    .collect()
+            }
+            Loss::MeanSquaredError => {
+                //確率についての勾配をSoftmaxのヤコビアンに通す
+                let targets = smoothed_targets(probabilities.len(), answer, 0.0);
+                let size = probabilities.len() as f64;
+                let gradients: Vec<f64> = probabilities
+                    .iter()
+                    .zip(&targets)
+                    .map(|(p, t)| 2.0 * (p - t) / size)
+                    .collect();
+                let weighted_sum: f64 = gradients
+                    .iter()
+                    .zip(probabilities)
+                    .map(|(g, p)| g * p)
+                    .sum();
+                probabilities
+                    .iter()
+                    .zip(&gradients)
+                    .map(|(p, g)| p * (g - weighted_sum))
+                    .collect()
+            }
+        }
+    }
+}
+
+fn smoothed_targets(size: usize, answer: usize, label_smoothing: f64) -> Vec<f64> {
+    let mut targets = vec![label_smoothing / size as f64; size];
+    targets[answer] += 1.0 - label_smoothing;
+    targets
+}
@@ -1,14 +1,37 @@
-mod binary_load;
-mod binary_save;
-mod light_network;
-mod network;
-
-use light_network::LightLayer;
-use network::Layer;
-use rand::{self, Rng};
+use glob::Pattern;
+use number_recognizer::architecture::Architecture;
+use number_recognizer::augmentation::Augmentation;
+use number_recognizer::batch_predict::{self, OutputFormat, PredictionWriter};
+use number_recognizer::canvas;
+use number_recognizer::conversion;
+use number_recognizer::convolution::IMAGE_SHAPE;
+use number_recognizer::cross_validation;
+use number_recognizer::dashboard::ProgressDisplay;
+use number_recognizer::dataset::{
+    self, Concat, Dataset, DatasetSource, IdxDataset, ImageFolderDataset, LoadMode, Mix,
+};
+use number_recognizer::detection::{self, DetectionSettings};
+use number_recognizer::image::GrayImage;
+use number_recognizer::input_normalization::{InputNormalization, NormalizationKind};
+use number_recognizer::inspection;
+use number_recognizer::light_network::{self, LightLayer};
+use number_recognizer::loss::Loss;
+use number_recognizer::network::Layer;
+use number_recognizer::preprocessing::Preprocessing;
+use number_recognizer::regularization::Regularization;
+use number_recognizer::search::{self, SearchData, SearchSpace, Strategy};
+use number_recognizer::segmentation;
+use number_recognizer::training::{self, TrainingSettings};
+use number_recognizer::{binary_load, binary_save, contact_sheet, image_file, user_dataset};
 use std::fs::{self, DirEntry, File};
-use std::io::{self, Write};
-use std::path::Path;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+//出力層の大きさ。ラベルはこれ未満でないと損失を求められない
+const NUM_CLASSES: usize = 10;
+
+const EMPTY_TRAIN_MESSAGE: &str =
+    "訓練用のデータが1枚もありません。検証用に取り分ける割合を小さくして下さい。";
 
 fn main() -> io::Result<()> {
     loop {
@@ -18,184 +41,115 @@ fn main() -> io::Result<()> {
 1 新しいモデルを作る
 2 モデルを読み込んで自動試験
 3 モデルを読み込んで手動試験
-4 終了"
+4 終了
+5 ハイパーパラメータを探索する
+6 交差検証でモデルの構造と設定を評価する
+7 データセットや判定する画像を画像ファイルとして書き出す
+8 ディレクトリ内の画像をまとめて判定する
+9 複数桁の数字が書かれた画像を判定する
+10 大きな画像の中から数字を探す
+11 データセットを検査する
+12 データセットの形式を変換する
+13 画面に数字を書いて判定する"
         );
         let _ = io::stdin().read_line(&mut input_menu);
         match input_menu.trim().parse::<u8>() {
             Ok(1) => {
-                println!(
-                    "入力層側から順に、各中間層に何個のニューロンを持たせるか空白区切りで入力して下さい。"
-                );
-                let mut _layer_sizes: Vec<usize> = Vec::new();
-                {
-                    loop {
-                        let mut input_num_layer = String::new();
-                        let _ = io::stdin().read_line(&mut input_num_layer);
-                        _layer_sizes = input_num_layer
-                            .split_whitespace()
-                            .map(|s| s.parse::<usize>().unwrap_or(0))
-                            .collect();
-                        if _layer_sizes.contains(&0) || _layer_sizes.contains(&1) {
-                            println!("予期されていない数値が入力されたので、入力し直して下さい。");
-                        } else {
-                            break;
-                        }
+                //続きから訓練する場合は、重みと一緒に訓練時の入力の正規化も引き継ぐ
+                let (mut layers, base_normalization) = match input_base_model() {
+                    Some((layers, normalization)) => (layers, Some(normalization)),
+                    None => {
+                        let architecture = input_architecture();
+                        println!("{}", architecture.summary());
+                        (architecture.build(), None)
                     }
-                }
-                //layer_sizesは入力層含む
-                //layersは入力層含まない
-                _layer_sizes.insert(0, 784);
-                _layer_sizes.push(10);
-                let num_layers = _layer_sizes.len() - 1;
-                let mut layers: Vec<Layer> = Vec::with_capacity(num_layers);
-                for i in 0..num_layers {
-                    let size_shallower_layer = *_layer_sizes.get(i).unwrap();
-                    let size_current_layer = *_layer_sizes.get(i + 1).unwrap();
-                    layers.push(Layer::new(
-                        size_current_layer,
-                        size_shallower_layer,
-                        i == num_layers - 1,
-                    ));
-                }
-                let mut image_file = File::open("datas/digits_image.bin")?;
-                let num_images = binary_load::get_num_of_images(&mut image_file)?;
-                let mut label_file = File::open("datas/digits_label.bin")?;
-                let _ = binary_load::get_num_of_labels(&mut label_file)?;
-
-                let mut all_images: Vec<Box<Vec<f64>>> = Vec::with_capacity(num_images);
-                for _ in 0..num_images {
-                    all_images.push(binary_load::get_next_image(&mut image_file)?);
-                }
-                let mut all_labels: Vec<u8> = Vec::with_capacity(num_images);
-                for _ in 0..num_images {
-                    all_labels.push(binary_load::get_next_label(&mut label_file)?);
-                }
+                };
+                let dataset = load_training_data()?;
 
-                let (size_batch, num_epoch, mut learning_rate) = loop {
-                    println!("バッチサイズとエポック数、学習率を空白区切りで入力して下さい。");
-                    let mut learning_parameters = String::new();
-                    let _ = io::stdin().read_line(&mut learning_parameters);
-                    let mut learning_parameters = learning_parameters.split_whitespace();
-                    if let Ok(size_batch) = learning_parameters.next().unwrap().parse::<usize>() {
-                        if let Ok(num_epoch) = learning_parameters.next().unwrap().parse::<usize>()
-                        {
-                            if let Ok(learning_rate) =
-                                learning_parameters.next().unwrap().parse::<f64>()
-                            {
-                                break (size_batch, num_epoch, learning_rate);
-                            } else {
-                                print!("学習率");
-                            }
-                        } else {
-                            print!("エポック数");
-                        }
-                    } else {
-                        print!("バッチサイズ");
-                    }
-                    println!("の値が不正です。再度入力して下さい。");
+                let (size_batch, num_epoch, learning_rate) = input_learning_parameters();
+                let loss = input_loss();
+                let regularization = input_regularization();
+                let augmentation = input_augmentation();
+                let normalization_kind = match base_normalization {
+                    Some(_) => None,
+                    None => Some(input_normalization_kind()),
                 };
-                let num_iteration: usize = num_images / size_batch;
-                //学習の進み具合に伴って学習率を小さくしていく為の係数
-                let learning_rate_coefficient =
-                    100f64.powf(1.0 / (num_iteration * num_epoch) as f64);
-                let mut rng = rand::thread_rng();
-                let mut cost = 0.0;
-                let mut label_debug: usize = 0;
-                for epoch in 0..num_epoch {
-                    for iteration in 0..num_iteration {
-                        for _batch in 0..size_batch {
-                            let data_index = rng.gen_range(0..num_images);
-                            let image = all_images.get(data_index).unwrap();
-                            let label = all_labels.get(data_index).unwrap();
-                            label_debug = *label as usize;
-                            cost = network::backpropagation(
-                                &mut layers,
-                                image,
-                                learning_rate,
-                                *label as usize,
-                            );
-                        }
-                        print!("\r\x1b[K");
-                        io::stdout().flush()?;
-                        print!(
-                            "cost: {:.4}\titeration: {}/{}\tepoch: {}/{}\tlearning rate: {:.8}\tanswer: {}\toutputs: ",
-                            cost,
-                            iteration,num_iteration,
-                            epoch,num_epoch,
-                            learning_rate,
-                            label_debug
+                println!("検証用に取り分けるデータの割合を0以上1未満で入力して下さい。");
+                let validation_rate = input_f64(|x| (0.0..1.0).contains(&x));
+                let (train, validation) =
+                    dataset::split(dataset.as_ref(), validation_rate, &mut rand::thread_rng());
+                if train.is_empty() {
+                    println!("{}", EMPTY_TRAIN_MESSAGE);
+                    continue;
+                }
+                //ユーザデータセットは訓練用の方にだけ混ぜる
+                let user_dataset = input_user_dataset()?;
+                let mixed;
+                let train: &dyn Dataset = match &user_dataset {
+                    Some((user_dataset, ratio)) => {
+                        mixed = Mix::new(&train, user_dataset, *ratio).map_err(|message| {
+                            io::Error::new(io::ErrorKind::InvalidData, message)
+                        })?;
+                        println!(
+                            "{}枚のユーザデータセットを混ぜ、合計{}枚で訓練します。",
+                            user_dataset.len(),
+                            mixed.len()
                         );
-                        for value in layers.last().unwrap().get_neurons_activations() {
-                            print!(" {:.3} ", value);
+                        &mixed
+                    }
+                    None => &train,
+                };
+                let normalization = match base_normalization {
+                    Some(normalization) => normalization,
+                    None => fit_normalization(normalization_kind.unwrap(), train)?,
+                };
+                let display = input_display();
+                let settings = TrainingSettings {
+                    size_batch,
+                    num_epoch,
+                    learning_rate,
+                    loss,
+                    regularization,
+                    display,
+                    augmentation,
+                    normalization,
+                };
+                let history = training::train(&mut layers, train, &validation, &settings)?;
+                match save_model(&layers, &settings.normalization) {
+                    Ok(model_path) => {
+                        println!("正常にモデルを保存できました。");
+                        if let Err(x) = history.save(&model_path) {
+                            println!("訓練の記録の保存中にエラーが発生しました:\n{}", x);
                         }
-                        io::stdout().flush().unwrap();
-                        network::apply_neurons_fixes(&mut layers, size_batch);
-                        learning_rate /= learning_rate_coefficient;
                     }
-                }
-                println!();
-                if let Err(x) = binary_save::save_model(&layers, &_layer_sizes[1..]) {
-                    println!("モデルの保存中にエラーが発生しました:\n{}", x);
-                    return Err(x);
-                } else {
-                    println!("正常にモデルを保存できました。");
+                    Err(x) => {
+                        println!("モデルの保存中にエラーが発生しました:\n{}", x);
+                        return Err(x);
+                    }
                 }
             }
             Ok(2) => {
-                let layer_sizes_len: usize;
-                let layer_sizes: Vec<usize>;
-                println!("読み込むモデルの名前を入力して下さい。");
-                let mut model_name = String::new();
-                'input_model_name: loop {
-                    let _ = io::stdin().read_line(&mut model_name);
-                    if let Ok(mut header_file) =
-                        File::open(format!("save_datas/{}/header.bin", model_name.trim()))
-                    {
-                        if let Ok((number, sizes)) = binary_load::load_header(&mut header_file) {
-                            layer_sizes_len = number;
-                            layer_sizes = sizes;
-                            break 'input_model_name;
-                        } else {
-                            panic!("モデルの読み込み中にエラーが発生しました。");
-                        }
-                    } else {
-                        println!(
-                            "その名前のモデルは存在しないかも知れません。もう一度入力して下さい。"
-                        );
-                        model_name.clear();
-                    }
-                }
-                let model_name = model_name.trim().to_string();
-                let mut layers: Vec<LightLayer> = Vec::with_capacity(layer_sizes_len);
-                for i in 0..layer_sizes_len {
-                    let path = Path::new("save_datas")
-                        .join(&model_name)
-                        .join(format!("layer{}.bin", i));
-                    let mut file = File::open(&path)?;
-                    layers.push(LightLayer::new(
-                        &mut file,
-                        *layer_sizes.get(i).unwrap(),
-                        if i == 0 {
-                            784
-                        } else {
-                            *layer_sizes.get(i - 1).unwrap()
-                        },
-                    ));
+                let mut layers = input_model();
+                let mut dataset = IdxDataset::open(
+                    Path::new("datas/digits_test_image.bin"),
+                    Path::new("datas/digits_test_label.bin"),
+                    LoadMode::Stream,
+                )?;
+                if !has_valid_labels(&dataset) {
+                    continue;
                 }
-                let mut test_image_file = File::open("datas/digits_test_image.bin")?;
-                let mut test_label_file = File::open("datas/digits_test_label.bin")?;
-                let num_test_images = binary_load::get_num_of_images(&mut test_image_file)?;
-                let num_test_labels = binary_load::get_num_of_labels(&mut test_label_file)?;
+                let loss = input_loss();
+                dataset.preprocessing = input_preprocessing();
 
                 let mut num_correct: u32 = 0;
                 let mut cost = 0f64;
-                for index_of_images in 0..num_test_images {
-                    print!("image: {}/{}", index_of_images, num_test_images);
+                for index_of_images in 0..dataset.len() {
+                    print!("image: {}/{}", index_of_images, dataset.len());
                     io::stdout().flush().unwrap();
-                    let test_image = binary_load::get_next_image(&mut test_image_file)?;
-                    let test_label = binary_load::get_next_label(&mut test_label_file)?;
+                    let test_image = dataset.image(index_of_images)?;
+                    let test_label = dataset.label(index_of_images);
                     light_network::guess_answer(&mut layers, &test_image);
-                    let last_layer_activations = layers.last().unwrap().get_neurons_activations();
+                    let last_layer_activations = layers.last().unwrap().get_activations();
                     let answer_of_network =
                         last_layer_activations
                             .iter()
@@ -207,81 +161,35 @@ fn main() -> io::Result<()> {
                                     max_i
                                 }
                             });
-                    cost -= last_layer_activations.get(answer_of_network).unwrap().ln();
+                    cost += loss.value(&last_layer_activations, test_label as usize);
                     if test_label == answer_of_network as u8 {
                         num_correct += 1;
                     }
                     print!("\r\x1b[K");
                     io::stdout().flush().unwrap();
                 }
-                cost /= num_test_labels as f64;
+                cost /= dataset.len() as f64;
                 println!(
-                    "cost: {:.5}\tcorrect answer rate: {:.1}%",
+                    "cost ({}): {:.5}\tcorrect answer rate: {:.1}%",
+                    loss.name(),
                     cost,
-                    100f64 * (num_correct as f64 / num_test_labels as f64)
+                    100f64 * (num_correct as f64 / dataset.len() as f64)
                 );
             }
             Ok(3) => {
-                let layer_sizes_len: usize;
-                let layer_sizes: Vec<usize>;
-                println!("読み込むモデルの名前を入力して下さい。");
-                let mut model_name = String::new();
-                'input_model_name: loop {
-                    let _ = io::stdin().read_line(&mut model_name);
-                    if let Ok(mut header_file) =
-                        File::open(format!("save_datas/{}/header.bin", model_name.trim()))
-                    {
-                        if let Ok((number, sizes)) = binary_load::load_header(&mut header_file) {
-                            layer_sizes_len = number;
-                            layer_sizes = sizes;
-                            break 'input_model_name;
-                        } else {
-                            panic!("モデルの読み込み中にエラーが発生しました。");
-                        }
-                    } else {
-                        println!(
-                            "その名前のモデルは存在しないかも知れません。もう一度入力して下さい。"
-                        );
-                        model_name.clear();
-                    }
-                }
-                let model_name = model_name.trim().to_string();
-                let mut layers: Vec<LightLayer> = Vec::with_capacity(layer_sizes_len);
-                for i in 0..layer_sizes_len {
-                    let path = Path::new("save_datas")
-                        .join(&model_name)
-                        .join(format!("layer{}.bin", i));
-                    let mut file = File::open(&path)?;
-                    layers.push(LightLayer::new(
-                        &mut file,
-                        *layer_sizes.get(i).unwrap(),
-                        if i == 0 {
-                            784
-                        } else {
-                            *layer_sizes.get(i - 1).unwrap()
-                        },
-                    ));
-                }
+                let mut layers = input_model();
 
                 let folder_path = Path::new("test_image");
                 let entries: Vec<DirEntry> = fs::read_dir(folder_path)?
                     .filter_map(Result::ok)
                     .filter(|e| e.path().is_file())
                     .collect();
-                let mut image_file: File;
-                match entries.len() {
+                let image_path = match entries.len() {
                     0 => {
                         println!("読み込めるファイルが見つかりませんでした。");
                         continue;
                     }
-                    1 => {
-                        if let Ok(file) = File::open(entries[0].path()) {
-                            image_file = file;
-                        } else {
-                            println!("ファイル読み込み時にエラーが発生しました。");
-                            continue;
-                        }
-                    }
+                    1 => entries[0].path(),
                     x => {
                         println!("{}個のファイルが見つかりました。\n0~{}の数字を入力し、ファイルを選んで下さい。",x,x-1);
                         for (i, entry) in entries.iter().enumerate() {
@@ -303,24 +211,26 @@ fn main() -> io::Result<()> {
                             }
                             println!("入力し直して下さい。");
                         }
-                        if let Ok(file) = File::open(entries[choice].path()) {
-                            image_file = file;
-                        } else {
-                            println!("ファイル読み込み時にエラーが発生しました。");
-                            continue;
-                        }
+                        entries[choice].path()
                     }
-                }
-                let image = binary_load::get_next_image(&mut image_file)?;
+                };
+                let preprocessing = input_preprocessing();
+                let image = match image_file::load_model_input(&image_path, &preprocessing) {
+                    Ok(image) => image,
+                    Err(x) => {
+                        println!("ファイル読み込み時にエラーが発生しました:\n{}", x);
+                        continue;
+                    }
+                };
                 light_network::guess_answer(&mut layers, &image);
-                let last_layer_activations = layers.last().unwrap().get_neurons_activations();
+                let last_layer_activations = layers.last().unwrap().get_activations();
                 let mut indexed_last_layer_activations: Vec<(usize, f64)> =
                     last_layer_activations.into_iter().enumerate().collect();
                 indexed_last_layer_activations.sort_by(|i, o| i.1.partial_cmp(&o.1).unwrap());
                 let answer_of_network = indexed_last_layer_activations.last().unwrap().0;
                 for (i, value) in image.iter().enumerate() {
                     print!("{}", if *value > 0.5 { "# " } else { "  " });
-                    if i % 28 == 0 {
+                    if (i + 1) % 28 == 0 {
                         println!();
                     }
                 }
@@ -329,15 +239,1029 @@ fn main() -> io::Result<()> {
                     println!("{} である確率: {: >7.3} %", index, output * 100f64);
                 }
                 println!("\n予測: これは {} です!", answer_of_network);
+                let name = image_path
+                    .file_stem()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                if let Err(x) = save_user_sample(&image, &name) {
+                    println!("画像の保存中にエラーが発生しました:\n{}", x);
+                }
             }
             Ok(4) => {
                 println!("終了します。");
                 break;
             }
-            Ok(_) => println!("1~4の数字を入力してください。"),
+            Ok(5) => {
+                println!("探索空間を書いたファイルのパスを入力して下さい。例: search_space.txt");
+                let space = loop {
+                    let mut input_path = String::new();
+                    let _ = io::stdin().read_line(&mut input_path);
+                    match fs::read_to_string(input_path.trim()) {
+                        Ok(text) => match SearchSpace::parse(&text) {
+                            Ok(space) => break space,
+                            Err(message) => println!("{}", message),
+                        },
+                        Err(x) => println!("ファイルを読み込めませんでした: {}", x),
+                    }
+                    println!("入力し直して下さい。");
+                };
+                println!(
+                    "探索の方法を選んで下さい。
+1 グリッドサーチ
+2 ランダムサーチ
+3 Successive Halving"
+                );
+                let strategy = loop {
+                    let mut input = String::new();
+                    let _ = io::stdin().read_line(&mut input);
+                    match input.trim().parse::<u8>() {
+                        Ok(1) if space.is_enumerable() => break Strategy::Grid,
+                        Ok(1) => println!(
+                            "グリッドサーチでは範囲ではなく候補を並べて下さい。他の方法を選んで下さい。"
+                        ),
+                        Ok(2) => {
+                            println!("試す回数を入力して下さい。");
+                            let num_trials = input_usize(|x| x >= 1);
+                            break Strategy::Random { num_trials };
+                        }
+                        Ok(3) => {
+                            println!("最初の候補の数を入力して下さい。");
+                            let num_candidates = input_usize(|x| x >= 1);
+                            println!("1段階ごとに候補を何分の1に減らすかを2以上で入力して下さい。");
+                            let reduction = input_usize(|x| x >= 2);
+                            break Strategy::SuccessiveHalving {
+                                num_candidates,
+                                reduction,
+                            };
+                        }
+                        _ => println!("1~3の数字を入力してください。"),
+                    }
+                };
+                let dataset = load_training_data()?;
+                let loss = input_loss();
+                let regularization = input_regularization();
+                let augmentation = input_augmentation();
+                let normalization_kind = input_normalization_kind();
+                println!("検証用に取り分けるデータの割合を0より大きく1未満で入力して下さい。");
+                let validation_rate = input_f64(|x| 0.0 < x && x < 1.0);
+                let (train, validation) =
+                    dataset::split(dataset.as_ref(), validation_rate, &mut rand::thread_rng());
+                if train.is_empty() {
+                    println!("{}", EMPTY_TRAIN_MESSAGE);
+                    continue;
+                }
+                let normalization = fit_normalization(normalization_kind, &train)?;
+                let data = SearchData {
+                    train: &train,
+                    validation: &validation,
+                    normalization: &normalization,
+                };
+                let result = match search::search(
+                    &space,
+                    &strategy,
+                    &data,
+                    &loss,
+                    &regularization,
+                    &augmentation,
+                )? {
+                    Ok(result) => result,
+                    Err(message) => {
+                        println!("{}", message);
+                        continue;
+                    }
+                };
+                println!();
+                result.print_leaderboard(10);
+                println!("1位のモデルを保存します。");
+                match save_model(&result.best_layers, &normalization) {
+                    Ok(model_path) => {
+                        println!("正常にモデルを保存できました。");
+                        if let Err(x) = result.best_history.save(&model_path).and_then(|_| {
+                            result.save_leaderboard(&model_path.join("leaderboard.csv"))
+                        }) {
+                            println!("訓練の記録の保存中にエラーが発生しました:\n{}", x);
+                        }
+                    }
+                    Err(x) => {
+                        println!("モデルの保存中にエラーが発生しました:\n{}", x);
+                        return Err(x);
+                    }
+                }
+            }
+            Ok(6) => {
+                let architecture = input_architecture();
+                println!("{}", architecture.summary());
+                let dataset = load_training_data()?;
+                let (size_batch, num_epoch, learning_rate) = input_learning_parameters();
+                let loss = input_loss();
+                let regularization = input_regularization();
+                let augmentation = input_augmentation();
+                let normalization_kind = input_normalization_kind();
+                println!("分割数Kを2以上で入力して下さい。");
+                let num_folds = input_usize(|x| x >= 2 && x <= dataset.len());
+                //正規化の統計量はフォールド毎に訓練に使う部分から求め直す
+                let mut settings = TrainingSettings {
+                    size_batch,
+                    num_epoch,
+                    learning_rate,
+                    loss,
+                    regularization,
+                    display: input_display(),
+                    augmentation,
+                    normalization: InputNormalization::default(),
+                };
+                let results = cross_validation::cross_validate(
+                    &architecture,
+                    &mut settings,
+                    normalization_kind,
+                    dataset.as_ref(),
+                    num_folds,
+                )?;
+                println!();
+                for (name, mean, std) in cross_validation::summarize(&results) {
+                    println!("{}: {:.4} ± {:.4}", name, mean, std);
+                }
+            }
+            Ok(7) => {
+                if let Err(x) = export_images() {
+                    println!("画像の書き出し中にエラーが発生しました:\n{}", x);
+                }
+            }
+            Ok(8) => {
+                if let Err(x) = predict_directory() {
+                    println!("一括判定中にエラーが発生しました:\n{}", x);
+                }
+            }
+            Ok(9) => {
+                if let Err(x) = recognize_number() {
+                    println!("判定中にエラーが発生しました:\n{}", x);
+                }
+            }
+            Ok(10) => {
+                if let Err(x) = detect_digits() {
+                    println!("数字を探している途中でエラーが発生しました:\n{}", x);
+                }
+            }
+            Ok(11) => {
+                if let Err(x) = inspect_dataset() {
+                    println!("検査中にエラーが発生しました:\n{}", x);
+                }
+            }
+            Ok(12) => {
+                if let Err(x) = convert_dataset() {
+                    println!("変換中にエラーが発生しました:\n{}", x);
+                }
+            }
+            Ok(13) => {
+                let mut layers = input_model();
+                let preprocessing = input_preprocessing();
+                if let Err(x) = canvas::run(&mut layers, &preprocessing) {
+                    println!("お絵描き帳の表示中にエラーが発生しました:\n{}", x);
+                }
+            }
+            Ok(_) => println!("1~13の数字を入力してください。"),
             Err(_) => println!("謎の文字を入力しないでください。"),
         }
         println!();
     }
     Ok(())
 }
+
+fn input_architecture() -> Architecture {
+    println!(
+        "モデルの構造を入力して下さい。例: conv(16,3)-maxpool(2)-dense(128,relu)-dropout(0.2)-dense(10)
+dense(大きさ[,活性化関数]): 全結合層
+conv(フィルタ数,カーネルの大きさ[,ストライド,パディング][,活性化関数]): 畳み込み層
+maxpool(大きさ[,ストライド]), avgpool(大きさ[,ストライド]): 最大値・平均値プーリング層
+flatten: 平坦化層
+dropout(率), batchnorm, layernorm: 直前の全結合層に付けるドロップアウトと正規化
+活性化関数: leaky_relu(既定), relu, sigmoid, tanh, linear
+最後は出力層のdense(10)にして下さい。"
+    );
+    loop {
+        let mut input_architecture = String::new();
+        let _ = io::stdin().read_line(&mut input_architecture);
+        match Architecture::parse(input_architecture.trim(), IMAGE_SHAPE) {
+            Ok(architecture) => return architecture,
+            Err(message) => println!("{}\n入力し直して下さい。", message),
+        }
+    }
+}
+
+//バッチサイズとエポック数は1以上
+fn input_learning_parameters() -> (usize, usize, f64) {
+    loop {
+        println!("バッチサイズとエポック数(どちらも1以上)、学習率を空白区切りで入力して下さい。");
+        let mut learning_parameters = String::new();
+        let _ = io::stdin().read_line(&mut learning_parameters);
+        let mut learning_parameters = learning_parameters.split_whitespace();
+        let mut next_positive = || {
+            learning_parameters
+                .next()
+                .and_then(|value| value.parse::<usize>().ok())
+                .filter(|&value| value >= 1)
+        };
+        if let Some(size_batch) = next_positive() {
+            if let Some(num_epoch) = next_positive() {
+                if let Some(learning_rate) = learning_parameters
+                    .next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                {
+                    return (size_batch, num_epoch, learning_rate);
+                } else {
+                    print!("学習率");
+                }
+            } else {
+                print!("エポック数");
+            }
+        } else {
+            print!("バッチサイズ");
+        }
+        println!("の値が不正です。再度入力して下さい。");
+    }
+}
+
+//標準出力が端末の時だけダッシュボードを選べる
+fn input_display() -> ProgressDisplay {
+    if io::stdout().is_terminal() {
+        println!("訓練中にダッシュボードを表示しますか? (y/n)");
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        if input.trim() == "y" {
+            ProgressDisplay::Dashboard
+        } else {
+            ProgressDisplay::Lines
+        }
+    } else {
+        ProgressDisplay::Lines
+    }
+}
+
+//訓練用データを1つ以上選び、複数なら順に繋げる
+fn load_training_data() -> io::Result<Box<dyn Dataset>> {
+    println!("訓練用の画像にも前処理をしますか? 試験の時と同じ前処理をするとよいです。");
+    let preprocessing = input_preprocessing();
+    let mut datasets: Vec<Box<dyn Dataset>> = Vec::new();
+    loop {
+        match input_dataset(preprocessing) {
+            Ok(dataset) if dataset.is_empty() => {
+                println!("画像が1枚もありません。別のデータを選んで下さい。");
+                continue;
+            }
+            Ok(dataset) if !has_valid_labels(dataset.as_ref()) => continue,
+            Ok(dataset) => {
+                println!(
+                    "{}枚の画像を読み込みました。クラスの数: {}",
+                    dataset.len(),
+                    dataset.num_classes()
+                );
+                datasets.push(dataset);
+            }
+            Err(x) => {
+                println!("訓練用データの読み込み中にエラーが発生しました:\n{}", x);
+                continue;
+            }
+        }
+        println!("さらに別の訓練用データを加えますか? (y/n)");
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        if input.trim() != "y" {
+            break;
+        }
+    }
+    if datasets.len() == 1 {
+        return Ok(datasets.pop().unwrap());
+    }
+    let dataset = Concat::new(datasets)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))?;
+    println!("合計{}枚の画像で訓練します。", dataset.len());
+    Ok(Box::new(dataset))
+}
+
+fn input_dataset(preprocessing: Preprocessing) -> io::Result<Box<dyn Dataset>> {
+    let source = input_dataset_source();
+    let mode = match source {
+        DatasetSource::Idx { .. } => input_load_mode(),
+        _ => LoadMode::Memory,
+    };
+    source.open(mode, preprocessing)
+}
+
+fn input_dataset_source() -> DatasetSource {
+    println!(
+        "データセットの種類を選んで下さい。
+1 訓練用のEMNIST(datas/digits_image.bin, datas/digits_label.bin)
+2 試験用のEMNIST(datas/digits_test_image.bin, datas/digits_test_label.bin)
+3 IDX形式の画像とラベルのファイル
+4 ラベルの名前のサブフォルダ(0~9)に画像を分けて入れたディレクトリ
+5 1列目がラベル、残りの784列が0~255の画素のCSVファイル"
+    );
+    let choice = input_usize(|x| (1..=5).contains(&x));
+    let input_path = |message: &str| {
+        println!("{}", message);
+        let mut path = String::new();
+        let _ = io::stdin().read_line(&mut path);
+        PathBuf::from(path.trim())
+    };
+    match choice {
+        1 => DatasetSource::Idx {
+            image_path: PathBuf::from("datas/digits_image.bin"),
+            label_path: PathBuf::from("datas/digits_label.bin"),
+        },
+        2 => DatasetSource::Idx {
+            image_path: PathBuf::from("datas/digits_test_image.bin"),
+            label_path: PathBuf::from("datas/digits_test_label.bin"),
+        },
+        3 => DatasetSource::Idx {
+            image_path: input_path("画像のファイルのパスを入力して下さい。"),
+            label_path: input_path("ラベルのファイルのパスを入力して下さい。"),
+        },
+        4 => DatasetSource::ImageFolder(input_path("ディレクトリのパスを入力して下さい。")),
+        _ => DatasetSource::Csv(input_path("CSVファイルのパスを入力して下さい。")),
+    }
+}
+
+fn input_load_mode() -> LoadMode {
+    println!(
+        "読み込み方を選んで下さい。
+1 メモリに読み込む(1画素1バイト)
+2 メモリマップする
+3 使う度にファイルから読む(メモリに載らない大きなデータセット向け)"
+    );
+    match input_usize(|x| (1..=3).contains(&x)) {
+        1 => LoadMode::Memory,
+        2 => LoadMode::Mapped,
+        _ => LoadMode::Stream,
+    }
+}
+
+fn input_preprocessing() -> Preprocessing {
+    println!(
+        "画像に掛ける前処理を、使うものの記号を並べて入力して下さい。例: icdt (前処理をしない時は何も入力しない)
+i 白地に黒い文字なら白黒を反転する
+d 傾きを補正する
+c 文字を切り出して20x20に収め、重心を中心に合わせる
+t 線の太さを揃える"
+    );
+    loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        if let Some(preprocessing) = Preprocessing::parse(input.trim()) {
+            return preprocessing;
+        }
+        println!("i, d, c, t以外の文字が入力されました。入力し直して下さい。");
+    }
+}
+
+//データセットの一部かtest_imageディレクトリのファイルを、モデルに入力する通りの向きと前処理で書き出す
+fn export_images() -> io::Result<()> {
+    println!(
+        "書き出すものを選んで下さい。
+1 訓練用データ
+2 試験用データ
+3 test_imageディレクトリのファイル"
+    );
+    let source = input_usize(|x| (1..=3).contains(&x));
+    let preprocessing = input_preprocessing();
+    //書き出す画像と一覧画像に書くラベル、ファイル名
+    let mut tiles: Vec<(GrayImage, String)> = Vec::new();
+    let mut file_names: Vec<String> = Vec::new();
+    if source == 3 {
+        let mut paths: Vec<PathBuf> = fs::read_dir("test_image")?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+        for (i, path) in paths.iter().enumerate() {
+            match image_file::load_model_input(path, &preprocessing) {
+                Ok(image) => {
+                    println!("{}: {:?}", i, path.file_name().unwrap());
+                    tiles.push((GrayImage::from_pixels(28, 28, image), i.to_string()));
+                    file_names.push(path.file_stem().unwrap().to_string_lossy().into_owned());
+                }
+                Err(x) => println!(
+                    "{:?}を読み込めませんでした: {}",
+                    path.file_name().unwrap(),
+                    x
+                ),
+            }
+        }
+    } else {
+        let (image_path, label_path) = if source == 1 {
+            ("datas/digits_image.bin", "datas/digits_label.bin")
+        } else {
+            ("datas/digits_test_image.bin", "datas/digits_test_label.bin")
+        };
+        let mut dataset = IdxDataset::open(
+            Path::new(image_path),
+            Path::new(label_path),
+            LoadMode::Stream,
+        )?;
+        dataset.preprocessing = preprocessing;
+        if dataset.is_empty() {
+            println!("{}には画像が1枚もありません。", image_path);
+            return Ok(());
+        }
+        let num_images = dataset.len();
+        println!(
+            "書き出す画像の番号の範囲を、0~{}の開始番号と終了番号を空白区切りで入力して下さい。例: 0 99",
+            num_images - 1
+        );
+        let (start, end) = loop {
+            let mut input = String::new();
+            let _ = io::stdin().read_line(&mut input);
+            let range: Vec<usize> = input
+                .split_whitespace()
+                .filter_map(|x| x.parse().ok())
+                .collect();
+            if let [start, end] = range[..] {
+                if start <= end && end < num_images {
+                    break (start, end);
+                }
+            }
+            println!("0~{}の範囲で入力し直して下さい。", num_images - 1);
+        };
+        for index in start..=end {
+            let image = dataset.image(index)?;
+            let label = dataset.label(index);
+            tiles.push((GrayImage::from_pixels(28, 28, image), label.to_string()));
+            file_names.push(format!("{}_{}", index, label));
+        }
+    }
+    if tiles.is_empty() {
+        println!("書き出せる画像がありませんでした。");
+        return Ok(());
+    }
+    println!(
+        "画像の形式を選んで下さい。
+1 PNG
+2 PGM"
+    );
+    let extension = if input_usize(|x| (1..=2).contains(&x)) == 1 {
+        "png"
+    } else {
+        "pgm"
+    };
+    println!("書き出し先のフォルダのパスを入力して下さい。例: exports");
+    let mut folder_path = String::new();
+    let _ = io::stdin().read_line(&mut folder_path);
+    let folder_path = Path::new(folder_path.trim());
+    fs::create_dir_all(folder_path)?;
+    for ((image, _), file_name) in tiles.iter().zip(&file_names) {
+        image_file::save_image(
+            &folder_path.join(format!("{}.{}", file_name, extension)),
+            image,
+        )?;
+    }
+    let sheet_path = folder_path.join(format!("contact_sheet.{}", extension));
+    image_file::save_image(&sheet_path, &contact_sheet::contact_sheet(&tiles, 10))?;
+    println!(
+        "{}枚の画像と、それらをラベル付きで並べた{}を書き出しました。",
+        tiles.len(),
+        sheet_path.display()
+    );
+    Ok(())
+}
+
+//ディレクトリ以下の画像を全て判定し、結果をCSVかJSON Linesで書き出す
+fn predict_directory() -> io::Result<()> {
+    let mut layers = input_model();
+    println!("判定する画像のあるディレクトリのパスを入力して下さい。サブディレクトリも探します。");
+    let mut dir_path = String::new();
+    let _ = io::stdin().read_line(&mut dir_path);
+    let dir_path = Path::new(dir_path.trim());
+    println!(
+        "判定するファイルのパターンを入力して下さい。例: *.png (何も入力しなければ全てのファイル)"
+    );
+    let pattern = loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        let input = if input.trim().is_empty() {
+            "*"
+        } else {
+            input.trim()
+        };
+        match Pattern::new(input) {
+            Ok(pattern) => break pattern,
+            Err(x) => println!("パターンが不正です: {}\n入力し直して下さい。", x),
+        }
+    };
+    let files = batch_predict::find_files(dir_path, &pattern)?;
+    if files.is_empty() {
+        println!("パターンに合うファイルが見つかりませんでした。");
+        return Ok(());
+    }
+    println!("{}個のファイルが見つかりました。", files.len());
+    let preprocessing = input_preprocessing();
+    println!("確率の高い順に何個まで出力するか1~10で入力して下さい。");
+    let k = input_usize(|x| (1..=10).contains(&x));
+    println!(
+        "出力の形式を選んで下さい。
+1 CSV
+2 JSON Lines"
+    );
+    let format = if input_usize(|x| (1..=2).contains(&x)) == 1 {
+        OutputFormat::Csv
+    } else {
+        OutputFormat::JsonLines
+    };
+    println!("結果を書き出すファイルのパスを入力して下さい。例: predictions.csv");
+    let mut output_path = String::new();
+    let _ = io::stdin().read_line(&mut output_path);
+    let output_file = BufWriter::new(File::create(output_path.trim())?);
+    let mut writer = PredictionWriter::new(output_file, format, k)?;
+    let mut num_predicted = 0;
+    let mut total_seconds = 0f64;
+    for (i, path) in files.iter().enumerate() {
+        print!("image: {}/{}", i, files.len());
+        io::stdout().flush().unwrap();
+        let prediction = batch_predict::predict_file(&mut layers, path, &preprocessing);
+        print!("\r\x1b[K");
+        match prediction {
+            Ok(prediction) => {
+                writer.write(&prediction)?;
+                num_predicted += 1;
+                total_seconds += prediction.inference_seconds;
+            }
+            Err(x) => println!("{}を判定できませんでした: {}", path.display(), x),
+        }
+    }
+    println!(
+        "{}個のファイルを判定し、結果を{}に書き出しました。推論時間の平均: {:.3}ms",
+        num_predicted,
+        output_path.trim(),
+        total_seconds * 1000.0 / num_predicted.max(1) as f64
+    );
+    Ok(())
+}
+
+//画像を1桁ずつに切り分けて判定し、繋げた数字と各桁の確率を表示する
+fn recognize_number() -> io::Result<()> {
+    let mut layers = input_model();
+    println!("複数桁の数字が書かれた画像ファイルのパスを入力して下さい。");
+    let mut image_path = String::new();
+    let _ = io::stdin().read_line(&mut image_path);
+    let image = image_file::load_image(Path::new(image_path.trim()))?;
+    println!("切り出した各桁には白黒の反転と切り出し・中心合わせを必ず掛けます。");
+    let preprocessing = input_preprocessing();
+    let number = segmentation::recognize_number(&mut layers, &image, &preprocessing);
+    if number.digits.is_empty() {
+        println!("数字が見つかりませんでした。");
+        return Ok(());
+    }
+    for (i, digit) in number.digits.iter().enumerate() {
+        let segment = digit.segment;
+        println!(
+            "{}桁目: 位置({}, {}) 大きさ{}x{} -> {} ({:.1}%)",
+            i + 1,
+            segment.left,
+            segment.top,
+            segment.width(),
+            segment.height(),
+            digit.label,
+            digit.confidence * 100.0
+        );
+    }
+    println!(
+        "\n予測: これは {} です! (全ての桁が合っている確率: {:.1}%)",
+        number.text(),
+        number.confidence() * 100.0
+    );
+    println!("切り出した各桁の画像を並べて保存する時はファイルのパスを入力して下さい。例: digits.png (保存しない時は何も入力しない)");
+    let mut sheet_path = String::new();
+    let _ = io::stdin().read_line(&mut sheet_path);
+    if !sheet_path.trim().is_empty() {
+        let tiles: Vec<(GrayImage, String)> = number
+            .digits
+            .iter()
+            .map(|digit| (digit.input.clone(), digit.label.to_string()))
+            .collect();
+        let sheet = contact_sheet::contact_sheet(&tiles, tiles.len());
+        image_file::save_image(Path::new(sheet_path.trim()), &sheet)?;
+    }
+    Ok(())
+}
+
+//大きさの違う窓で画像を走査して数字を探し、枠とラベル、確率を表示する
+fn detect_digits() -> io::Result<()> {
+    let mut layers = input_model();
+    println!("数字を探す画像ファイルのパスを入力して下さい。");
+    let mut image_path = String::new();
+    let _ = io::stdin().read_line(&mut image_path);
+    let image = image_file::load_image(Path::new(image_path.trim()))?;
+    let mut settings = DetectionSettings::default_for(&image);
+    println!(
+        "一番小さい窓の一辺(画素)、窓を大きくする倍率、窓をずらす幅の割合、確率の閾値、重なりを許すIoUの上限を空白区切りで入力して下さい。
+何も入力しなければ {} {} {} {} {} を使います。",
+        settings.min_window_size,
+        settings.scale_factor,
+        settings.stride_rate,
+        settings.score_threshold,
+        settings.iou_threshold
+    );
+    loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        if input.trim().is_empty() {
+            break;
+        }
+        let values: Vec<f64> = input
+            .split_whitespace()
+            .filter_map(|x| x.parse().ok())
+            .collect();
+        if let [min_window_size, scale_factor, stride_rate, score_threshold, iou_threshold] =
+            values[..]
+        {
+            if min_window_size >= 1.0
+                && scale_factor > 1.0
+                && stride_rate > 0.0
+                && (0.0..=1.0).contains(&score_threshold)
+                && (0.0..=1.0).contains(&iou_threshold)
+            {
+                settings.min_window_size = min_window_size as usize;
+                settings.scale_factor = scale_factor;
+                settings.stride_rate = stride_rate;
+                settings.score_threshold = score_threshold;
+                settings.iou_threshold = iou_threshold;
+                break;
+            }
+        }
+        println!("値が不正です。5つの値を入力し直して下さい。");
+    }
+    let mut detections = detection::detect(&mut layers, &image, &settings);
+    if detections.is_empty() {
+        println!("数字が見つかりませんでした。");
+        return Ok(());
+    }
+    detections.sort_by_key(|detection| (detection.left, detection.top));
+    println!("{}個の数字が見つかりました。", detections.len());
+    for detection in &detections {
+        println!(
+            "位置({}, {}) 大きさ{}x{} -> {} ({:.1}%)",
+            detection.left,
+            detection.top,
+            detection.size,
+            detection.size,
+            detection.label,
+            detection.score * 100.0
+        );
+    }
+    println!("枠を書き込んだ画像を保存する時はファイルのパスを入力して下さい。例: detections.png (保存しない時は何も入力しない)");
+    let mut annotated_path = String::new();
+    let _ = io::stdin().read_line(&mut annotated_path);
+    if !annotated_path.trim().is_empty() {
+        let annotated = detection::annotate(&image, &detections);
+        image_file::save_image(Path::new(annotated_path.trim()), &annotated)?;
+    }
+    Ok(())
+}
+
+fn inspect_dataset() -> io::Result<()> {
+    let source = input_dataset_source();
+    //IDX形式はヘッダが壊れていると開けないので、先にヘッダとファイルの長さだけを調べる
+    if let DatasetSource::Idx {
+        image_path,
+        label_path,
+    } = &source
+    {
+        let problems = inspection::check_idx_files(image_path, label_path)?;
+        if problems.is_empty() {
+            println!("ファイルのヘッダに問題はありません。");
+        } else {
+            println!("ファイルのヘッダに問題があります:");
+            for problem in &problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+    let dataset = source.open(LoadMode::Stream, Preprocessing::default())?;
+    println!("{}枚の画像を調べています...", dataset.len());
+    let report = inspection::inspect(dataset.as_ref(), 10);
+    report.print();
+    if !report.has_problems() {
+        println!("問題は見つかりませんでした。");
+    }
+    Ok(())
+}
+
+fn convert_dataset() -> io::Result<()> {
+    println!("まず変換元のデータセットを選びます。");
+    let source = input_dataset_source();
+    let preprocessing = input_preprocessing();
+    println!(
+        "変換先の形式を選んで下さい。
+1 IDX形式の画像とラベルのファイル
+2 1列目がラベル、残りの784列が0~255の画素のCSVファイル
+3 ラベルの名前のサブフォルダにPNG画像を分けて入れたディレクトリ"
+    );
+    let choice = input_usize(|x| (1..=3).contains(&x));
+    let input_path = |message: &str| {
+        println!("{}", message);
+        let mut path = String::new();
+        let _ = io::stdin().read_line(&mut path);
+        PathBuf::from(path.trim())
+    };
+    let target = match choice {
+        1 => DatasetSource::Idx {
+            image_path: input_path("書き出す画像のファイルのパスを入力して下さい。"),
+            label_path: input_path("書き出すラベルのファイルのパスを入力して下さい。"),
+        },
+        2 => DatasetSource::Csv(input_path("書き出すCSVファイルのパスを入力して下さい。")),
+        _ => DatasetSource::ImageFolder(input_path("書き出すディレクトリのパスを入力して下さい。")),
+    };
+    let source_paths = source.paths();
+    if target
+        .paths()
+        .iter()
+        .any(|path| source_paths.contains(path))
+    {
+        println!("変換元と同じ場所には書き出せません。");
+        return Ok(());
+    }
+    println!(
+        "EMNISTの.binファイルと同じく縦横を入れ替えて書き出しますか? (y/n)
+このプログラムはIDX形式を読む時に転置するので、IDX形式ならy、それ以外ならnで元と同じ向きに読み込めます。"
+    );
+    let mut input = String::new();
+    let _ = io::stdin().read_line(&mut input);
+    let transpose = input.trim() == "y";
+    let dataset = source.open(LoadMode::Stream, preprocessing)?;
+    println!("{}枚の画像を変換しています...", dataset.len());
+    let count = conversion::convert(dataset.as_ref(), &target, transpose)?;
+    println!("{}枚の画像を書き出しました。", count);
+    Ok(())
+}
+
+//保存したモデルの続きから訓練するなら、そのネットワークと入力の正規化
+fn input_base_model() -> Option<(Vec<Layer>, InputNormalization)> {
+    println!(
+        "保存したモデルの続きから訓練する場合はモデルの名前を、新しく作る場合は何も入力せずにEnterを押して下さい。"
+    );
+    loop {
+        let mut model_name = String::new();
+        let _ = io::stdin().read_line(&mut model_name);
+        let model_name = model_name.trim();
+        if model_name.is_empty() {
+            return None;
+        }
+        let model_path = Path::new("save_datas").join(model_name);
+        match binary_load::load_model(model_name)
+            .and_then(|layers| Ok((layers, InputNormalization::load(&model_path)?)))
+        {
+            Ok((layers, normalization)) => {
+                println!(
+                    "{}\n入力の正規化: {}",
+                    Architecture::from_layers(IMAGE_SHAPE, &layers).summary(),
+                    normalization.kind().name()
+                );
+                return Some((layers, normalization));
+            }
+            Err(x) => println!(
+                "モデルを読み込めませんでした: {}\nもう一度入力して下さい。",
+                x
+            ),
+        }
+    }
+}
+
+//ユーザデータセットがあれば、訓練用データに混ぜる割合を聞く。混ぜないならNone
+//ラベルが全て0~NUM_CLASSES-1か。範囲外があれば知らせる
+fn has_valid_labels(dataset: &dyn Dataset) -> bool {
+    let num_classes = dataset.num_classes();
+    if num_classes > NUM_CLASSES {
+        println!(
+            "ラベルが0~{}の範囲外のデータがあります(最大のラベル: {})。11でデータセットを検査して下さい。",
+            NUM_CLASSES - 1,
+            num_classes - 1
+        );
+    }
+    num_classes <= NUM_CLASSES
+}
+
+fn input_user_dataset() -> io::Result<Option<(ImageFolderDataset, f64)>> {
+    let Some(dataset) = user_dataset::open()? else {
+        return Ok(None);
+    };
+    if !has_valid_labels(&dataset) {
+        return Ok(None);
+    }
+    println!(
+        "{}ディレクトリに{}枚のユーザデータセットがあります。訓練用データのうちユーザデータセットにする割合を0以上1未満で入力して下さい(0なら混ぜません)。",
+        user_dataset::USER_DATASET_DIR,
+        dataset.len()
+    );
+    let ratio = input_f64(|x| (0.0..1.0).contains(&x));
+    Ok((ratio > 0.0).then_some((dataset, ratio)))
+}
+
+//手動試験で判定した画像に正しいラベルを付けてユーザデータセットに加える
+fn save_user_sample(image: &[f64], name: &str) -> io::Result<()> {
+    println!(
+        "正しいラベルを付けてユーザデータセットに保存する場合は0~9の数字を、保存しない場合は何も入力せずにEnterを押して下さい。"
+    );
+    loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(());
+        }
+        match input.parse::<u8>() {
+            Ok(label) if label <= 9 => {
+                let path = user_dataset::save_sample(image, label, name)?;
+                println!("{}に保存しました。", path.display());
+                return Ok(());
+            }
+            _ => println!("0~9の数字を入力し直して下さい。"),
+        }
+    }
+}
+
+fn input_model() -> Vec<LightLayer> {
+    println!("読み込むモデルの名前を入力して下さい。");
+    loop {
+        let mut model_name = String::new();
+        let _ = io::stdin().read_line(&mut model_name);
+        if let Ok(layers) = light_network::load_model(model_name.trim()) {
+            return layers;
+        }
+        println!("その名前のモデルは存在しないかも知れません。もう一度入力して下さい。");
+    }
+}
+
+fn input_loss() -> Loss {
+    println!(
+        "損失関数を選んで下さい。
+1 交差エントロピー
+2 ラベル平滑化付き交差エントロピー
+3 クラス重み付き交差エントロピー
+4 Focal loss
+5 平均二乗誤差"
+    );
+    loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        match input.trim().parse::<u8>() {
+            Ok(1) => {
+                return Loss::CrossEntropy {
+                    label_smoothing: 0.0,
+                }
+            }
+            Ok(2) => {
+                println!("平滑化の係数を0以上1未満で入力して下さい。");
+                let label_smoothing = input_f64(|x| (0.0..1.0).contains(&x));
+                return Loss::CrossEntropy { label_smoothing };
+            }
+            Ok(3) => {
+                println!("0~9の各クラスの重みを空白区切りで入力して下さい。");
+                loop {
+                    let mut input = String::new();
+                    let _ = io::stdin().read_line(&mut input);
+                    let class_weights: Vec<f64> = input
+                        .split_whitespace()
+                        .map(|s| s.parse::<f64>().unwrap_or(-1.0))
+                        .collect();
+                    if class_weights.len() == 10 && class_weights.iter().all(|&w| w >= 0.0) {
+                        return Loss::WeightedCrossEntropy { class_weights };
+                    }
+                    println!("0以上の数値を10個入力し直して下さい。");
+                }
+            }
+            Ok(4) => {
+                println!("gammaの値を0以上で入力して下さい。");
+                let gamma = input_f64(|x| x >= 0.0);
+                return Loss::Focal { gamma };
+            }
+            Ok(5) => return Loss::MeanSquaredError,
+            _ => println!("1~5の数字を入力してください。"),
+        }
+    }
+}
+
+fn input_regularization() -> Regularization {
+    println!(
+        "L2正則化の係数、L1正則化の係数、max-normの上限を空白区切りで入力して下さい。
+使わないものは0にして下さい。"
+    );
+    let mut regularization = loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        let values: Vec<f64> = input
+            .split_whitespace()
+            .map(|s| s.parse::<f64>().unwrap_or(-1.0))
+            .collect();
+        if values.len() == 3 && values.iter().all(|&x| x >= 0.0) {
+            break Regularization {
+                l2: values[0],
+                l1: values[1],
+                max_norm: if values[2] > 0.0 {
+                    Some(values[2])
+                } else {
+                    None
+                },
+                include_bias: false,
+            };
+        }
+        println!("0以上の数値を3個入力し直して下さい。");
+    };
+    if regularization.l2 > 0.0 || regularization.l1 > 0.0 {
+        println!("バイアスにも正則化を適用しますか? (y/n)");
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        regularization.include_bias = input.trim() == "y";
+    }
+    regularization
+}
+
+fn input_augmentation() -> Augmentation {
+    println!(
+        "訓練中に掛けるデータ拡張を 種類(確率,強さ) を-で繋いで入力して下さい。使わない場合は何も入力せずにEnterを押して下さい。
+例: affine(0.5,1)-elastic(0.3,1)-noise(0.3,0.1)
+affine: 回転(強さ1で±15度)・拡大縮小(±10%)・せん断(±0.2)・平行移動(±2画素)
+elastic: 弾性変形(強さ1で変位の大きさ34)
+noise: ガウス雑音(強さは標準偏差)
+erasing: 長方形の消去(強さは消す面積の割合の上限)
+thickness: 線の太さの変更(強さ1で1画素分)
+contrast: コントラスト(強さ1で0.5~1.5倍)と明るさ(±0.1)の変更"
+    );
+    let mut augmentation = loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        match Augmentation::parse(input.trim()) {
+            Ok(augmentation) => break augmentation,
+            Err(message) => println!("{}\n入力し直して下さい。", message),
+        }
+    };
+    println!(
+        "乱数の種を入力して下さい。毎回違う乱数にする場合は何も入力せずにEnterを押して下さい。"
+    );
+    loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        let input = input.trim();
+        if input.is_empty() {
+            break;
+        }
+        if let Ok(seed) = input.parse::<u64>() {
+            augmentation.seed = Some(seed);
+            break;
+        }
+        println!("0以上の整数を入力し直して下さい。");
+    }
+    augmentation
+}
+
+fn input_normalization_kind() -> NormalizationKind {
+    println!(
+        "入力の正規化を選んで下さい。統計量は訓練用データから求め、モデルと一緒に保存します。"
+    );
+    for (i, kind) in NormalizationKind::ALL.iter().enumerate() {
+        println!("{} {}", i + 1, kind.name());
+    }
+    NormalizationKind::ALL[input_usize(|x| (1..=NormalizationKind::ALL.len()).contains(&x)) - 1]
+}
+
+fn fit_normalization(
+    kind: NormalizationKind,
+    dataset: &dyn Dataset,
+) -> io::Result<InputNormalization> {
+    if kind != NormalizationKind::UnitRange && kind != NormalizationKind::Symmetric {
+        println!("正規化の統計量を求めています...");
+    }
+    InputNormalization::fit(kind, dataset)
+}
+
+//モデルと入力の正規化を保存し、保存したフォルダのパスを返す
+fn save_model(layers: &[Layer], normalization: &InputNormalization) -> io::Result<PathBuf> {
+    let model_path = binary_save::save_model(layers)?;
+    normalization.save(&model_path)?;
+    Ok(model_path)
+}
+
+fn input_usize(is_valid: impl Fn(usize) -> bool) -> usize {
+    loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        if let Ok(x) = input.trim().parse::<usize>() {
+            if is_valid(x) {
+                return x;
+            }
+        }
+        println!("値が不正です。再度入力して下さい。");
+    }
+}
+
+fn input_f64(is_valid: impl Fn(f64) -> bool) -> f64 {
+    loop {
+        let mut input = String::new();
+        let _ = io::stdin().read_line(&mut input);
+        if let Ok(x) = input.trim().parse::<f64>() {
+            if is_valid(x) {
+                return x;
+            }
+        }
+        println!("値が不正です。再度入力して下さい。");
+    }
+}
@@ -1,117 +1,431 @@
+use crate::convolution::{ConvolutionLayer, PoolingLayer, Shape};
+use crate::loss::Loss;
+use crate::regularization::Regularization;
+use rand::Rng;
 use rand_distr::{Distribution, Normal};
 
-pub fn guess_answer(layers: &mut Vec<Layer>, image: &Vec<f64>) {
-    let layers_last_i = layers.len() - 1;
+//正規化の分母が0にならないようにする為の値
+pub const NORMALIZATION_EPSILON: f64 = 1e-5;
+//バッチ正規化の移動平均・移動分散を更新する割合
+const RUNNING_MOMENTUM: f64 = 0.1;
+
+//順伝播の種類
+#[derive(Clone, Copy, PartialEq)]
+enum Pass {
+    //ドロップアウトを掛け、バッチ正規化にミニバッチの統計量を使って移動平均・移動分散を更新する
+    Training,
+    //Trainingと同じ計算をするが、移動平均・移動分散は更新しない。勾配の確認で損失を求める為に使う
+    Probe,
+    //判定時。ドロップアウトを掛けず、バッチ正規化に移動平均・移動分散を使う
+    Inference,
+}
+
+pub fn guess_answer(layers: &mut [Layer], image: &[f64]) {
+    propagate_forward(layers, &[image.to_vec()], Pass::Inference);
+}
+
+fn propagate_forward(layers: &mut [Layer], images: &[Vec<f64>], pass: Pass) {
     let shallowest_layer = layers.first_mut().unwrap();
-    shallowest_layer.set_neurons_activations(image, layers_last_i == 0);
+    shallowest_layer.set_activations(images, pass);
     let mut iter_layers = layers.iter_mut();
     let mut shallower_layer = iter_layers.next().unwrap();
-    let mut current_layer_i: usize = 1;
     for current_layer in iter_layers {
-        current_layer.set_neurons_activations(
-            &shallower_layer.get_neurons_activations(),
-            current_layer_i == layers_last_i,
-        );
+        current_layer.set_activations(&shallower_layer.get_activations(), pass);
         shallower_layer = current_layer;
-        current_layer_i += 1;
     }
 
     //SOFTMAX
-    let mut exp_sum = 0f64;
-    for ref mut neuron in &mut layers.last_mut().unwrap().neurons {
-        neuron.activation = f64::exp(neuron.activation);
-        exp_sum += neuron.activation;
-    }
-    for ref mut neuron in &mut layers.last_mut().unwrap().neurons {
-        neuron.activation /= exp_sum;
+    let Layer::Dense(output_layer) = layers.last_mut().unwrap() else {
+        panic!("出力層は全結合層でなければいけません。");
+    };
+    let output_neurons = &mut output_layer.neurons;
+    for sample_i in 0..images.len() {
+        let mut exp_sum = 0f64;
+        for neuron in output_neurons.iter_mut() {
+            neuron.activations[sample_i] = f64::exp(neuron.activations[sample_i]);
+            exp_sum += neuron.activations[sample_i];
+        }
+        for neuron in output_neurons.iter_mut() {
+            neuron.activations[sample_i] /= exp_sum;
+        }
     }
 }
 
+//ミニバッチ1つ分の各サンプルの勾配の和を溜め、損失の平均を返す
 pub fn backpropagation(
-    layers: &mut Vec<Layer>,
-    image: &Vec<f64>,
-    learning_rate: f64,
-    answer: usize,
+    layers: &mut [Layer],
+    images: &[Vec<f64>],
+    answers: &[usize],
+    loss: &Loss,
 ) -> f64 {
-    guess_answer(layers, image);
-    let mut ideal_activations = [0f64; 10];
-    ideal_activations[answer] = 1.0;
-    for (i, neuron) in layers.last_mut().unwrap().neurons.iter_mut().enumerate() {
-        neuron.delta = neuron.activation - ideal_activations.get(i).unwrap();
+    propagate_forward(layers, images, Pass::Training);
+    let all_probabilities = layers.last().unwrap().get_activations();
+    let mut deltas: Vec<Vec<f64>> = all_probabilities
+        .iter()
+        .zip(answers)
+        .map(|(probabilities, &answer)| loss.output_deltas(probabilities, answer))
+        .collect();
+    for layer_i in (0..layers.len()).rev() {
+        let shallower_activations = if layer_i == 0 {
+            images.to_vec()
+        } else {
+            layers[layer_i - 1].get_activations()
+        };
+        deltas = layers[layer_i].stack_corrections(&shallower_activations, &deltas);
     }
-    let mut rev_layers = layers.iter_mut().rev();
-    let mut current_layer = rev_layers.next().unwrap();
-    let mut is_output_layer = true;
-    for shallower_layer in rev_layers {
-        for ref mut neuron in &mut current_layer.neurons {
-            neuron.stack_correction_activations(
-                &mut shallower_layer.neurons,
-                learning_rate,
-                is_output_layer,
-            );
+    mean_loss(&all_probabilities, answers, loss)
+}
+
+//訓練時と同じ順伝播をして損失の平均を返す。修正量は溜めず、バッチ正規化の移動平均・移動分散も変えない
+pub fn batch_loss(
+    layers: &mut [Layer],
+    images: &[Vec<f64>],
+    answers: &[usize],
+    loss: &Loss,
+) -> f64 {
+    propagate_forward(layers, images, Pass::Probe);
+    mean_loss(&layers.last().unwrap().get_activations(), answers, loss)
+}
+
+fn mean_loss(all_probabilities: &[Vec<f64>], answers: &[usize], loss: &Loss) -> f64 {
+    all_probabilities
+        .iter()
+        .zip(answers)
+        .map(|(probabilities, &answer)| loss.value(probabilities, answer))
+        .sum::<f64>()
+        / answers.len() as f64
+}
+
+//学習するパラメータを浅い層から順に、溜まった勾配と組にして渡す
+pub fn for_each_parameter(layers: &mut [Layer], mut f: impl FnMut(&mut f64, &mut f64)) {
+    for layer in layers {
+        match layer {
+            Layer::Dense(layer) => {
+                let has_normalization = layer.normalization != Normalization::None;
+                for neuron in &mut layer.neurons {
+                    for (weight, fix_weight) in
+                        neuron.weights.iter_mut().zip(&mut neuron.fix_weights)
+                    {
+                        f(weight, fix_weight);
+                    }
+                    f(&mut neuron.bias, &mut neuron.fix_bias);
+                    if has_normalization {
+                        f(&mut neuron.gamma, &mut neuron.fix_gamma);
+                        f(&mut neuron.beta, &mut neuron.fix_beta);
+                    }
+                }
+            }
+            Layer::Convolution(layer) => {
+                for filter in &mut layer.filters {
+                    for (parameter, fix) in filter.parameters_mut() {
+                        f(parameter, fix);
+                    }
+                }
+            }
+            Layer::Pooling(_) | Layer::Flatten(_) => {}
         }
-        current_layer = shallower_layer;
-        is_output_layer = false;
     }
-    for ref mut neuron in &mut current_layer.neurons {
-        neuron.stack_correction_activations_shallowest_layer(image, learning_rate, is_output_layer);
+}
+
+//全結合層の各ニューロンのバッチ正規化の(移動平均, 移動分散)を浅い層から順に渡す
+pub fn for_each_running_statistic(layers: &mut [Layer], mut f: impl FnMut(&mut f64, &mut f64)) {
+    for layer in layers {
+        if let Layer::Dense(layer) = layer {
+            for neuron in &mut layer.neurons {
+                f(&mut neuron.running_mean, &mut neuron.running_variance);
+            }
+        }
     }
-    -layers
-        .last()
-        .unwrap()
-        .neurons
-        .get(answer)
-        .unwrap()
-        .activation
-        .ln()
 }
 
-pub fn apply_neurons_fixes(layers: &mut Vec<Layer>, size_batch: usize) {
-    for ref mut layer in layers {
-        for ref mut neuron in &mut layer.neurons {
-            neuron.apply_fixes(size_batch);
+//溜まった勾配をバッチの平均にした時のL2ノルム
+pub fn gradient_norm(layers: &mut [Layer], size_batch: usize) -> f64 {
+    let mut sum = 0f64;
+    for_each_parameter(layers, |_, fix| sum += fix.powi(2));
+    sum.sqrt() / size_batch as f64
+}
+
+pub fn apply_neurons_fixes(
+    layers: &mut [Layer],
+    size_batch: usize,
+    learning_rate: f64,
+    regularization: &Regularization,
+) {
+    for layer in layers {
+        match layer {
+            Layer::Dense(layer) => {
+                for neuron in &mut layer.neurons {
+                    neuron.apply_fixes(size_batch, learning_rate, regularization);
+                }
+            }
+            Layer::Convolution(layer) => {
+                for filter in &mut layer.filters {
+                    filter.apply_fixes(size_batch, learning_rate, regularization);
+                }
+            }
+            Layer::Pooling(_) | Layer::Flatten(_) => {}
         }
     }
 }
 
-pub struct Layer {
-    pub neurons: Vec<Neuron>,
+pub fn regularization_penalty(layers: &[Layer], regularization: &Regularization) -> f64 {
+    layers
+        .iter()
+        .map(|layer| match layer {
+            Layer::Dense(layer) => layer
+                .neurons
+                .iter()
+                .map(|neuron| regularization.penalty(&neuron.weights, neuron.bias))
+                .sum(),
+            Layer::Convolution(layer) => layer
+                .filters
+                .iter()
+                .map(|filter| filter.penalty(regularization))
+                .sum(),
+            Layer::Pooling(_) | Layer::Flatten(_) => 0f64,
+        })
+        .sum()
+}
+
+pub enum Layer {
+    Dense(DenseLayer),
+    Convolution(ConvolutionLayer),
+    Pooling(PoolingLayer),
+    Flatten(FlattenLayer),
 }
 
 impl Layer {
+    //各サンプルを平たく並べた時の出力の大きさ
+    pub fn output_size(&self) -> usize {
+        match self {
+            Layer::Dense(layer) => layer.neurons.len(),
+            Layer::Convolution(layer) => layer.output_shape().size(),
+            Layer::Pooling(layer) => layer.output_shape().size(),
+            Layer::Flatten(layer) => layer.input_shape.size(),
+        }
+    }
+
+    fn set_activations(&mut self, ref_shallower_activations: &[Vec<f64>], pass: Pass) {
+        match self {
+            Layer::Dense(layer) => layer.set_neurons_activations(ref_shallower_activations, pass),
+            Layer::Convolution(layer) => layer.set_activations(ref_shallower_activations),
+            Layer::Pooling(layer) => layer.set_activations(ref_shallower_activations),
+            Layer::Flatten(layer) => layer.activations = ref_shallower_activations.to_vec(),
+        }
+    }
+
+    //サンプル毎の出力
+    pub fn get_activations(&self) -> Vec<Vec<f64>> {
+        match self {
+            Layer::Dense(layer) => layer.get_neurons_activations(),
+            Layer::Convolution(layer) => layer.get_activations(),
+            Layer::Pooling(layer) => layer.get_activations(),
+            Layer::Flatten(layer) => layer.activations.clone(),
+        }
+    }
+
+    fn stack_corrections(
+        &mut self,
+        ref_shallower_activations: &[Vec<f64>],
+        deltas: &[Vec<f64>],
+    ) -> Vec<Vec<f64>> {
+        match self {
+            Layer::Dense(layer) => layer.stack_corrections(ref_shallower_activations, deltas),
+            Layer::Convolution(layer) => layer.stack_corrections(ref_shallower_activations, deltas),
+            Layer::Pooling(layer) => layer.stack_corrections(deltas),
+            Layer::Flatten(_) => deltas.to_vec(),
+        }
+    }
+}
+
+//特徴マップを全結合層に渡す為の層。データは元々平たく並べてあるので形の情報だけを持つ
+pub struct FlattenLayer {
+    pub input_shape: Shape,
+    activations: Vec<Vec<f64>>,
+}
+
+impl FlattenLayer {
+    pub fn new(input_shape: Shape) -> FlattenLayer {
+        FlattenLayer {
+            input_shape,
+            activations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Normalization {
+    None,
+    //ニューロン毎にミニバッチ内で正規化する。判定時は移動平均・移動分散を使う
+    Batch,
+    //サンプル毎に層内の全ニューロンで正規化する
+    Layer,
+}
+
+pub struct DenseLayer {
+    pub neurons: Vec<Neuron>,
+    pub activation: Activation,
+    //逆ドロップアウトで各ニューロンを落とす確率
+    pub dropout_rate: f64,
+    pub normalization: Normalization,
+    //レイヤー正規化で使ったサンプル毎の標準偏差
+    sample_stds: Vec<f64>,
+}
+
+impl DenseLayer {
     pub fn new(
         size_this_layer: usize,
         size_shallower_layer: usize,
         is_output_layer: bool,
-    ) -> Layer {
+        rng: &mut impl Rng,
+    ) -> DenseLayer {
         let mut neurons: Vec<Neuron> = Vec::with_capacity(size_this_layer);
         for _ in 0..size_this_layer {
             neurons.push(if is_output_layer {
-                Neuron::new_xavier(size_shallower_layer, size_this_layer)
+                Neuron::new_xavier(size_shallower_layer, size_this_layer, rng)
             } else {
-                Neuron::new_he(size_shallower_layer)
+                Neuron::new_he(size_shallower_layer, rng)
             });
         }
-        Layer { neurons }
+        DenseLayer {
+            neurons,
+            activation: if is_output_layer {
+                Activation::Identity
+            } else {
+                Activation::LeakyRelu
+            },
+            dropout_rate: 0f64,
+            normalization: Normalization::None,
+            sample_stds: Vec::new(),
+        }
     }
 
-    fn set_neurons_activations(
-        &mut self,
-        ref_shallower_activations: &Vec<f64>,
-        is_output_layer: bool,
-    ) {
+    fn set_neurons_activations(&mut self, ref_shallower_activations: &[Vec<f64>], pass: Pass) {
+        let size_batch = ref_shallower_activations.len();
+        for neuron in &mut self.neurons {
+            neuron.set_weighted_sums(ref_shallower_activations);
+        }
+        match self.normalization {
+            Normalization::None => {
+                for neuron in &mut self.neurons {
+                    neuron.pre_activations.clone_from(&neuron.weighted_sums);
+                }
+            }
+            Normalization::Batch => {
+                for neuron in &mut self.neurons {
+                    neuron.batch_normalize(pass);
+                }
+            }
+            Normalization::Layer => {
+                let size = self.neurons.len() as f64;
+                self.sample_stds.clear();
+                for sample_i in 0..size_batch {
+                    let mean = self
+                        .neurons
+                        .iter()
+                        .map(|neuron| neuron.weighted_sums[sample_i])
+                        .sum::<f64>()
+                        / size;
+                    let variance = self
+                        .neurons
+                        .iter()
+                        .map(|neuron| (neuron.weighted_sums[sample_i] - mean).powi(2))
+                        .sum::<f64>()
+                        / size;
+                    let std = (variance + NORMALIZATION_EPSILON).sqrt();
+                    for neuron in &mut self.neurons {
+                        neuron.normalized[sample_i] = (neuron.weighted_sums[sample_i] - mean) / std;
+                        neuron.pre_activations[sample_i] =
+                            neuron.gamma * neuron.normalized[sample_i] + neuron.beta;
+                    }
+                    self.sample_stds.push(std);
+                }
+            }
+        }
+        let mut rng = rand::thread_rng();
+        let keep_rate = 1.0 - self.dropout_rate;
         for neuron in &mut self.neurons {
-            neuron.set_activation(ref_shallower_activations, is_output_layer);
+            for dropout_scale in &mut neuron.dropout_scales {
+                *dropout_scale = if pass == Pass::Inference || self.dropout_rate == 0.0 {
+                    1.0
+                } else if rng.gen_bool(keep_rate) {
+                    1.0 / keep_rate
+                } else {
+                    0.0
+                };
+            }
+            neuron.set_activations(self.activation);
         }
     }
 
+    //サンプル毎の各ニューロンのアクティベーション
     #[inline]
-    pub fn get_neurons_activations(&self) -> Vec<f64> {
-        self.neurons
-            .iter()
-            .map(|neuron| neuron.activation)
+    pub fn get_neurons_activations(&self) -> Vec<Vec<f64>> {
+        let size_batch = self.neurons.first().map_or(0, |n| n.activations.len());
+        (0..size_batch)
+            .map(|sample_i| {
+                self.neurons
+                    .iter()
+                    .map(|neuron| neuron.activations[sample_i])
+                    .collect()
+            })
             .collect()
     }
+
+    //deltasはこの層の出力についての損失の勾配。浅い層の出力についての勾配を返す
+    fn stack_corrections(
+        &mut self,
+        ref_shallower_activations: &[Vec<f64>],
+        deltas: &[Vec<f64>],
+    ) -> Vec<Vec<f64>> {
+        for (neuron_i, neuron) in self.neurons.iter_mut().enumerate() {
+            for (delta, sample_deltas) in neuron.deltas.iter_mut().zip(deltas) {
+                *delta = sample_deltas[neuron_i];
+            }
+            neuron.stack_correction_activation_function(self.activation);
+        }
+        match self.normalization {
+            Normalization::None => {}
+            Normalization::Batch => {
+                for neuron in &mut self.neurons {
+                    neuron.stack_correction_normalization_parameters();
+                    neuron.stack_correction_batch_normalization();
+                }
+            }
+            Normalization::Layer => {
+                let size = self.neurons.len() as f64;
+                for neuron in &mut self.neurons {
+                    neuron.stack_correction_normalization_parameters();
+                }
+                for (sample_i, std) in self.sample_stds.iter().enumerate() {
+                    let delta_sum: f64 = self
+                        .neurons
+                        .iter()
+                        .map(|neuron| neuron.gamma * neuron.deltas[sample_i])
+                        .sum();
+                    let delta_dot_normalized: f64 = self
+                        .neurons
+                        .iter()
+                        .map(|neuron| {
+                            neuron.gamma * neuron.deltas[sample_i] * neuron.normalized[sample_i]
+                        })
+                        .sum();
+                    for neuron in &mut self.neurons {
+                        neuron.deltas[sample_i] = (size * neuron.gamma * neuron.deltas[sample_i]
+                            - delta_sum
+                            - neuron.normalized[sample_i] * delta_dot_normalized)
+                            / (size * std);
+                    }
+                }
+            }
+        }
+        let mut shallower_deltas =
+            vec![vec![0f64; ref_shallower_activations[0].len()]; deltas.len()];
+        for neuron in &mut self.neurons {
+            neuron.stack_correction_weights(ref_shallower_activations, &mut shallower_deltas);
+        }
+        shallower_deltas
+    }
 }
 
 pub struct Neuron {
@@ -119,123 +433,313 @@ pub struct Neuron {
     fix_weights: Vec<f64>,
     bias: f64,
     fix_bias: f64,
-    pre_activation: f64,
-    activation: f64,
-    delta: f64,
+    //正規化後のスケールとシフト
+    gamma: f64,
+    fix_gamma: f64,
+    beta: f64,
+    fix_beta: f64,
+    running_mean: f64,
+    running_variance: f64,
+    batch_std: f64,
+    //以下はミニバッチ内のサンプル毎の値
+    weighted_sums: Vec<f64>,
+    normalized: Vec<f64>,
+    pre_activations: Vec<f64>,
+    activations: Vec<f64>,
+    deltas: Vec<f64>,
+    dropout_scales: Vec<f64>,
 }
 
 impl Neuron {
     #[inline]
-    fn new_he(size_shallower_layer: usize) -> Neuron {
+    fn new_he(size_shallower_layer: usize, rng: &mut impl Rng) -> Neuron {
         let normal = Normal::new(0f64, (2.0 / size_shallower_layer as f64).sqrt()).unwrap();
-        let mut rng = rand::thread_rng();
-        Neuron {
-            weights: (0..size_shallower_layer)
-                .map(|_| normal.sample(&mut rng))
-                .collect(),
-            fix_weights: vec![0f64; size_shallower_layer],
-            bias: 0f64,
-            fix_bias: 0f64,
-            pre_activation: 0f64,
-            activation: 0f64,
-            delta: 0f64,
-        }
+        Neuron::new(size_shallower_layer, normal, rng)
     }
 
     #[inline]
-    fn new_xavier(size_shallower_layer: usize, size_this_layer: usize) -> Neuron {
+    fn new_xavier(
+        size_shallower_layer: usize,
+        size_this_layer: usize,
+        rng: &mut impl Rng,
+    ) -> Neuron {
         let normal = Normal::new(
             0f64,
             (2.0 / (size_this_layer + size_shallower_layer) as f64).sqrt(),
         )
         .unwrap();
-        let mut rng = rand::thread_rng();
+        Neuron::new(size_shallower_layer, normal, rng)
+    }
+
+    fn new(size_shallower_layer: usize, normal: Normal<f64>, rng: &mut impl Rng) -> Neuron {
         Neuron {
             weights: (0..size_shallower_layer)
-                .map(|_| normal.sample(&mut rng))
+                .map(|_| normal.sample(rng))
                 .collect(),
             fix_weights: vec![0f64; size_shallower_layer],
             bias: 0f64,
             fix_bias: 0f64,
-            pre_activation: 0f64,
-            activation: 0f64,
-            delta: 0f64,
+            gamma: 1f64,
+            fix_gamma: 0f64,
+            beta: 0f64,
+            fix_beta: 0f64,
+            running_mean: 0f64,
+            running_variance: 1f64,
+            batch_std: 1f64,
+            weighted_sums: Vec::new(),
+            normalized: Vec::new(),
+            pre_activations: Vec::new(),
+            activations: Vec::new(),
+            deltas: Vec::new(),
+            dropout_scales: Vec::new(),
         }
     }
 
-    fn set_activation(&mut self, ref_shallower_activations: &Vec<f64>, is_output_layer: bool) {
-        self.pre_activation = 0.0;
-        for (weight, shallower_activation) in self.weights.iter().zip(ref_shallower_activations) {
-            self.pre_activation += weight * shallower_activation;
+    fn set_weighted_sums(&mut self, ref_shallower_activations: &[Vec<f64>]) {
+        let size_batch = ref_shallower_activations.len();
+        self.weighted_sums.clear();
+        for shallower_activations in ref_shallower_activations {
+            let mut weighted_sum = self.bias;
+            for (weight, shallower_activation) in self.weights.iter().zip(shallower_activations) {
+                weighted_sum += weight * shallower_activation;
+            }
+            self.weighted_sums.push(weighted_sum);
         }
-        self.pre_activation += self.bias;
-        self.activation = if is_output_layer {
-            self.pre_activation
+        self.normalized.resize(size_batch, 0f64);
+        self.pre_activations.resize(size_batch, 0f64);
+        self.activations.resize(size_batch, 0f64);
+        self.deltas.resize(size_batch, 0f64);
+        self.dropout_scales.resize(size_batch, 1f64);
+    }
+
+    fn batch_normalize(&mut self, pass: Pass) {
+        let (mean, variance) = if pass != Pass::Inference {
+            let size_batch = self.weighted_sums.len() as f64;
+            let mean = self.weighted_sums.iter().sum::<f64>() / size_batch;
+            let variance = self
+                .weighted_sums
+                .iter()
+                .map(|x| (x - mean).powi(2))
+                .sum::<f64>()
+                / size_batch;
+            if pass == Pass::Training {
+                self.running_mean += RUNNING_MOMENTUM * (mean - self.running_mean);
+                self.running_variance += RUNNING_MOMENTUM * (variance - self.running_variance);
+            }
+            (mean, variance)
         } else {
-            leaky_relu(self.pre_activation)
+            (self.running_mean, self.running_variance)
+        };
+        self.batch_std = (variance + NORMALIZATION_EPSILON).sqrt();
+        for ((normalized, pre_activation), weighted_sum) in self
+            .normalized
+            .iter_mut()
+            .zip(&mut self.pre_activations)
+            .zip(&self.weighted_sums)
+        {
+            *normalized = (weighted_sum - mean) / self.batch_std;
+            *pre_activation = self.gamma * *normalized + self.beta;
         }
     }
 
-    #[inline]
-    fn stack_correction_activations(
-        &mut self,
-        ref_shallower_neurons: &mut Vec<Neuron>,
-        learning_rate: f64,
-        is_output_layer: bool,
-    ) {
-        let mut learning_amount = learning_rate * self.delta;
-        if !is_output_layer {
-            learning_amount *= derivative_leaky_relu(self.pre_activation);
+    fn set_activations(&mut self, activation_function: Activation) {
+        for ((activation, pre_activation), dropout_scale) in self
+            .activations
+            .iter_mut()
+            .zip(&self.pre_activations)
+            .zip(&self.dropout_scales)
+        {
+            *activation = activation_function.apply(*pre_activation) * dropout_scale;
         }
-        for ((shallower_neuron, fix_weight), weight) in ref_shallower_neurons
+    }
+
+    //deltasを活性化関数の入力についての勾配にする
+    fn stack_correction_activation_function(&mut self, activation_function: Activation) {
+        for ((delta, pre_activation), dropout_scale) in self
+            .deltas
             .iter_mut()
-            .zip(self.fix_weights.iter_mut())
-            .zip(self.weights.iter())
+            .zip(&self.pre_activations)
+            .zip(&self.dropout_scales)
         {
-            *fix_weight += learning_amount * shallower_neuron.activation;
-            shallower_neuron.delta += self.delta * weight;
+            *delta *= dropout_scale * activation_function.derivative(*pre_activation);
+        }
+    }
+
+    fn stack_correction_normalization_parameters(&mut self) {
+        for (delta, normalized) in self.deltas.iter().zip(&self.normalized) {
+            self.fix_gamma += delta * normalized;
+            self.fix_beta += delta;
+        }
+    }
+
+    //deltasを重み付き和についての勾配にする
+    fn stack_correction_batch_normalization(&mut self) {
+        let size_batch = self.deltas.len() as f64;
+        let gamma = self.gamma;
+        let delta_sum: f64 = self.deltas.iter().map(|delta| gamma * delta).sum();
+        let delta_dot_normalized: f64 = self
+            .deltas
+            .iter()
+            .zip(&self.normalized)
+            .map(|(delta, normalized)| gamma * delta * normalized)
+            .sum();
+        for (delta, normalized) in self.deltas.iter_mut().zip(&self.normalized) {
+            *delta = (size_batch * gamma * *delta - delta_sum - normalized * delta_dot_normalized)
+                / (size_batch * self.batch_std);
         }
-        self.fix_bias += learning_amount;
-        self.delta = 0f64;
     }
 
-    fn stack_correction_activations_shallowest_layer(
+    fn stack_correction_weights(
         &mut self,
-        image: &Vec<f64>,
-        learning_rate: f64,
-        is_output_layer: bool,
+        ref_shallower_activations: &[Vec<f64>],
+        shallower_deltas: &mut [Vec<f64>],
     ) {
-        let mut learning_amount = learning_rate * self.delta;
-        if !is_output_layer {
-            learning_amount *= derivative_leaky_relu(self.pre_activation);
-        }
-        for (image_pixel, fix_weight) in image.iter().zip(self.fix_weights.iter_mut()) {
-            *fix_weight += learning_amount * image_pixel;
+        for ((delta, shallower_activations), sample_shallower_deltas) in self
+            .deltas
+            .iter()
+            .zip(ref_shallower_activations)
+            .zip(shallower_deltas.iter_mut())
+        {
+            for (((fix_weight, weight), shallower_activation), shallower_delta) in self
+                .fix_weights
+                .iter_mut()
+                .zip(&self.weights)
+                .zip(shallower_activations)
+                .zip(sample_shallower_deltas.iter_mut())
+            {
+                *fix_weight += delta * shallower_activation;
+                *shallower_delta += delta * weight;
+            }
+            self.fix_bias += delta;
         }
-        self.fix_bias += learning_amount;
-        self.delta = 0f64;
     }
 
-    fn apply_fixes(&mut self, size_batch: usize) {
-        let size_batch = size_batch as f64;
+    fn apply_fixes(
+        &mut self,
+        size_batch: usize,
+        learning_rate: f64,
+        regularization: &Regularization,
+    ) {
+        let step = learning_rate / size_batch as f64;
+        //罰則項の勾配はサンプル毎ではなく1回分なのでバッチサイズで割らない
         for (weight, fix_waight) in self.weights.iter_mut().zip(&mut self.fix_weights) {
-            *weight -= *fix_waight / size_batch;
+            *weight -= step * *fix_waight + learning_rate * regularization.gradient(*weight);
             *fix_waight = 0f64;
         }
-        self.bias -= self.fix_bias / size_batch;
+        self.bias -= step * self.fix_bias + learning_rate * regularization.bias_gradient(self.bias);
         self.fix_bias = 0f64;
+        self.gamma -= step * self.fix_gamma;
+        self.fix_gamma = 0f64;
+        self.beta -= step * self.fix_beta;
+        self.fix_beta = 0f64;
+        regularization.constrain(&mut self.weights);
     }
 
-    pub fn get_parameters(&self) -> (&Vec<f64>, f64) {
-        (&self.weights, self.fix_bias)
+    //バッチ正規化を使っている場合は移動平均・移動分散を重みとバイアスに畳み込んで返す
+    pub fn get_parameters(&self, normalization: Normalization) -> (Vec<f64>, f64) {
+        if normalization == Normalization::Batch {
+            let scale = self.gamma / (self.running_variance + NORMALIZATION_EPSILON).sqrt();
+            (
+                self.weights.iter().map(|weight| weight * scale).collect(),
+                (self.bias - self.running_mean) * scale + self.beta,
+            )
+        } else {
+            (self.weights.clone(), self.bias)
+        }
+    }
+
+    pub fn get_layer_normalization_parameters(&self) -> (f64, f64) {
+        (self.gamma, self.beta)
+    }
+
+    //保存したモデルの続きから訓練する時に、読み込んだ値で置き換える
+    pub fn set_parameters(&mut self, weights: Vec<f64>, bias: f64) {
+        self.weights = weights;
+        self.bias = bias;
+    }
+
+    pub fn set_layer_normalization_parameters(&mut self, gamma: f64, beta: f64) {
+        self.gamma = gamma;
+        self.beta = beta;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Activation {
+    LeakyRelu,
+    Relu,
+    Sigmoid,
+    Tanh,
+    //出力層はこれにしてSoftmaxを掛ける
+    Identity,
+}
+
+impl Activation {
+    pub fn from_name(name: &str) -> Option<Activation> {
+        match name {
+            "leaky_relu" => Some(Activation::LeakyRelu),
+            "relu" => Some(Activation::Relu),
+            "sigmoid" => Some(Activation::Sigmoid),
+            "tanh" => Some(Activation::Tanh),
+            "linear" => Some(Activation::Identity),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Activation::LeakyRelu => "leaky_relu",
+            Activation::Relu => "relu",
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::Identity => "linear",
+        }
+    }
+
+    pub fn apply(self, x: f64) -> f64 {
+        match self {
+            Activation::LeakyRelu => leaky_relu(x),
+            Activation::Relu => x.max(0.0),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Tanh => x.tanh(),
+            Activation::Identity => x,
+        }
+    }
+
+    //活性化関数の入力xについての微分
+    pub fn derivative(self, x: f64) -> f64 {
+        match self {
+            Activation::LeakyRelu => derivative_leaky_relu(x),
+            Activation::Relu => {
+                if x >= 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Activation::Sigmoid => {
+                let y = 1.0 / (1.0 + (-x).exp());
+                y * (1.0 - y)
+            }
+            Activation::Tanh => 1.0 - x.tanh().powi(2),
+            Activation::Identity => 1.0,
+        }
     }
 }
 
 //LEAKY RELU
 pub fn leaky_relu(x: f64) -> f64 {
-    if x >= 0.0 { x } else { x * -0.04 }
+    if x >= 0.0 {
+        x
+    } else {
+        x * -0.04
+    }
 }
 
 pub fn derivative_leaky_relu(x: f64) -> f64 {
-    if x >= 0.0 { 1.0 } else { -0.04 }
+    if x >= 0.0 {
+        1.0
+    } else {
+        -0.04
+    }
 }
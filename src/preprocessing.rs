@@ -0,0 +1,229 @@
+use crate::image::GrayImage;
+
+//EMNISTと同じように文字を20x20に収め、重心を28x28の中心に合わせる
+const IMAGE_SIZE: usize = 28;
+const DIGIT_SIZE: usize = 20;
+//これより明るい画素を文字の一部とみなす(一番明るい画素との比)
+const INK_THRESHOLD: f64 = 0.1;
+//20x20に収めた時の線の太さの目安(画素)
+const TARGET_THICKNESS: f64 = 2.5;
+const THICKNESS_TOLERANCE: f64 = 0.5;
+const MAX_THICKNESS_STEPS: usize = 3;
+
+//手書き画像をEMNISTの画像に近づける前処理。各段階は個別に切り替えられる
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Preprocessing {
+    //背景の方が明るければ白黒を反転する
+    pub auto_invert: bool,
+    //画像のモーメントから求めた傾きを打ち消す
+    pub deskew: bool,
+    //文字の外接長方形を切り出し、縦横比を保って20x20に収め、重心を28x28の中心に置く
+    pub center: bool,
+    //線の太さをEMNISTの平均的な太さに近づける
+    pub normalize_thickness: bool,
+}
+
+impl Preprocessing {
+    //各段階を表す文字を並べた文字列から作る。i: 自動反転 d: 傾き補正 c: 切り出しと中心合わせ t: 太さの正規化
+    pub fn parse(flags: &str) -> Option<Preprocessing> {
+        let mut preprocessing = Preprocessing::default();
+        for flag in flags.chars().filter(|c| !c.is_whitespace()) {
+            match flag {
+                'i' => preprocessing.auto_invert = true,
+                'd' => preprocessing.deskew = true,
+                'c' => preprocessing.center = true,
+                't' => preprocessing.normalize_thickness = true,
+                _ => return None,
+            }
+        }
+        Some(preprocessing)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self != Preprocessing::default()
+    }
+
+    //28x28の画像に掛ける
+    pub fn apply(&self, pixels: &[f64]) -> Vec<f64> {
+        let image = GrayImage::from_pixels(IMAGE_SIZE, IMAGE_SIZE, pixels.to_vec());
+        self.apply_to_image(&image).pixels
+    }
+
+    //任意の大きさの画像から28x28の画像を作る。中心合わせをしない時は単に縮小する
+    pub fn apply_to_image(&self, image: &GrayImage) -> GrayImage {
+        let mut image = image.clone();
+        if self.auto_invert {
+            image = auto_invert(&image);
+        }
+        if self.deskew {
+            //切り出さない時は後で縮小するので、幅を広げると文字が横に潰れる
+            image = deskew(&image, self.center);
+        }
+        if self.center {
+            if let Some(digit) = fit_digit(&image) {
+                image = digit;
+            }
+        } else if image.width != IMAGE_SIZE || image.height != IMAGE_SIZE {
+            image = image.resize(IMAGE_SIZE, IMAGE_SIZE);
+        }
+        if self.normalize_thickness {
+            image = normalize_thickness(&image);
+        }
+        if self.center {
+            image = center_by_mass(&image);
+        }
+        image
+    }
+}
+
+//縁の画素の平均が半分より明るければ白地に黒い文字とみなす
+pub fn auto_invert(image: &GrayImage) -> GrayImage {
+    let mut border = Vec::new();
+    for x in 0..image.width {
+        border.push(image.get(x, 0));
+        border.push(image.get(x, image.height - 1));
+    }
+    for y in 0..image.height {
+        border.push(image.get(0, y));
+        border.push(image.get(image.width - 1, y));
+    }
+    let mean = border.iter().sum::<f64>() / border.len() as f64;
+    if mean <= 0.5 {
+        return image.clone();
+    }
+    let mut inverted = image.clone();
+    for pixel in &mut inverted.pixels {
+        *pixel = 1.0 - *pixel;
+    }
+    inverted
+}
+
+//2次の中心モーメントから傾きを求め、重心の高さを軸に横方向へずらして打ち消す
+//widenなら端がはみ出さないように幅を広げ、そうでなければ元の幅のままにする(重心の位置は変わらない)
+fn deskew(image: &GrayImage, widen: bool) -> GrayImage {
+    let Some((cx, cy)) = image.center_of_mass() else {
+        return image.clone();
+    };
+    let (mut mu11, mut mu02) = (0f64, 0f64);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let value = image.get(x, y);
+            mu11 += value * (x as f64 - cx) * (y as f64 - cy);
+            mu02 += value * (y as f64 - cy).powi(2);
+        }
+    }
+    if mu02 < 1e-2 {
+        return image.clone();
+    }
+    let skew = mu11 / mu02;
+    let (min_shift, width) = if widen {
+        let shifts = [skew * -cy, skew * (image.height as f64 - 1.0 - cy)];
+        let min_shift = shifts[0].min(shifts[1]);
+        let max_shift = shifts[0].max(shifts[1]);
+        (
+            min_shift,
+            image.width + (max_shift - min_shift).ceil() as usize,
+        )
+    } else {
+        (0.0, image.width)
+    };
+    let mut deskewed = GrayImage::new(width, image.height);
+    for y in 0..image.height {
+        let shift = skew * (y as f64 - cy);
+        for x in 0..width {
+            let source_x = x as f64 + min_shift + shift;
+            deskewed.set(x, y, image.bilinear(source_x, y as f64));
+        }
+    }
+    deskewed
+}
+
+//外接長方形を切り出し、縦横比を保ってDIGIT_SIZEに収めた画像をIMAGE_SIZEの中央に置く
+fn fit_digit(image: &GrayImage) -> Option<GrayImage> {
+    let max = image.pixels.iter().copied().fold(0f64, f64::max);
+    let (x, y, width, height) = image.bounding_box(max * INK_THRESHOLD)?;
+    let cropped = image.crop(x, y, width, height);
+    let scale = DIGIT_SIZE as f64 / width.max(height) as f64;
+    let new_width = ((width as f64 * scale).round() as usize).clamp(1, DIGIT_SIZE);
+    let new_height = ((height as f64 * scale).round() as usize).clamp(1, DIGIT_SIZE);
+    let digit = cropped.resize(new_width, new_height);
+    let mut fitted = GrayImage::new(IMAGE_SIZE, IMAGE_SIZE);
+    let (left, top) = ((IMAGE_SIZE - new_width) / 2, (IMAGE_SIZE - new_height) / 2);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            fitted.set(left + x, top + y, digit.get(x, y));
+        }
+    }
+    Some(fitted)
+}
+
+//重心が画像の中心に来るよう整数画素だけ平行移動する
+fn center_by_mass(image: &GrayImage) -> GrayImage {
+    let Some((cx, cy)) = image.center_of_mass() else {
+        return image.clone();
+    };
+    let center = (IMAGE_SIZE as f64 - 1.0) / 2.0;
+    let (dx, dy) = (
+        (center - cx).round() as isize,
+        (center - cy).round() as isize,
+    );
+    let mut centered = GrayImage::new(image.width, image.height);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            if (0..image.width as isize).contains(&nx) && (0..image.height as isize).contains(&ny) {
+                centered.set(nx as usize, ny as usize, image.get(x, y));
+            }
+        }
+    }
+    centered
+}
+
+//線の太さを面積/輪郭の長さの2倍で見積もる
+fn estimate_thickness(image: &GrayImage) -> f64 {
+    let area: f64 = image.pixels.iter().sum();
+    let mut perimeter = 0f64;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let value = image.get(x, y);
+            let right = if x + 1 < image.width {
+                image.get(x + 1, y)
+            } else {
+                0.0
+            };
+            let below = if y + 1 < image.height {
+                image.get(x, y + 1)
+            } else {
+                0.0
+            };
+            perimeter += (right - value).abs() + (below - value).abs();
+        }
+    }
+    if perimeter > 0.0 {
+        2.0 * area / perimeter
+    } else {
+        0.0
+    }
+}
+
+//3x3の最大値(膨張)か最小値(収縮)との間を混ぜ、太さを少しずつ目安に近づける
+//3x3の膨張・収縮はおよそ2画素ずつ太さを変えるので、差の半分の割合で混ぜる
+fn normalize_thickness(image: &GrayImage) -> GrayImage {
+    let mut image = image.clone();
+    for _ in 0..MAX_THICKNESS_STEPS {
+        let thickness = estimate_thickness(&image);
+        if thickness == 0.0 || (thickness - TARGET_THICKNESS).abs() <= THICKNESS_TOLERANCE {
+            break;
+        }
+        let extreme = if thickness > TARGET_THICKNESS {
+            image.erode()
+        } else {
+            image.dilate()
+        };
+        let rate = ((thickness - TARGET_THICKNESS).abs() / 2.0).min(1.0);
+        for (value, target) in image.pixels.iter_mut().zip(&extreme.pixels) {
+            *value += rate * (target - *value);
+        }
+    }
+    image
+}
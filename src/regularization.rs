@@ -0,0 +1,62 @@
+#[derive(Clone)]
+pub struct Regularization {
+    pub l2: f64,
+    pub l1: f64,
+    //各ニューロンの重みベクトルのノルムの上限
+    pub max_norm: Option<f64>,
+    pub include_bias: bool,
+}
+
+impl Regularization {
+    pub fn none() -> Regularization {
+        Regularization {
+            l2: 0.0,
+            l1: 0.0,
+            max_norm: None,
+            include_bias: false,
+        }
+    }
+
+    //損失に加える罰則項
+    pub fn penalty(&self, weights: &[f64], bias: f64) -> f64 {
+        let bias = if self.include_bias { Some(&bias) } else { None };
+        weights
+            .iter()
+            .chain(bias)
+            .map(|w| 0.5 * self.l2 * w * w + self.l1 * w.abs())
+            .sum()
+    }
+
+    //罰則項の重みwでの微分。勾配に足してから重みを更新する
+    pub fn gradient(&self, w: f64) -> f64 {
+        let sign = if w > 0.0 {
+            1.0
+        } else if w < 0.0 {
+            -1.0
+        } else {
+            0.0
+        };
+        self.l2 * w + self.l1 * sign
+    }
+
+    //バイアスは既定では対象外なので0
+    pub fn bias_gradient(&self, bias: f64) -> f64 {
+        if self.include_bias {
+            self.gradient(bias)
+        } else {
+            0.0
+        }
+    }
+
+    //更新した後の重みベクトルのノルムをmax_norm以下に縮める
+    pub fn constrain(&self, weights: &mut [f64]) {
+        if let Some(max_norm) = self.max_norm {
+            let norm = weights.iter().map(|w| w * w).sum::<f64>().sqrt();
+            if norm > max_norm {
+                for w in weights {
+                    *w *= max_norm / norm;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use crate::architecture::Architecture;
+use crate::augmentation::Augmentation;
+use crate::convolution::IMAGE_SHAPE;
+use crate::dashboard::ProgressDisplay;
+use crate::dataset::Dataset;
+use crate::history::TrainingHistory;
+use crate::input_normalization::InputNormalization;
+use crate::loss::Loss;
+use crate::network::Layer;
+use crate::regularization::Regularization;
+use crate::training::{self, TrainingSettings};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+//探索空間の書式。1行に1つのハイパーパラメータを書き、#から始まる行は無視する
+//architecture = dense(64)-dense(10) | dense(128,relu)-dense(10)
+//size_batch = 16 | 32
+//num_epoch = 1 | 2
+//learning_rate = 0.001..0.1
+//候補は|で区切って並べる。数値はa..bと書くと範囲になり、無作為に選ぶ時だけ使える(学習率は対数で一様に選ぶ)
+pub struct SearchSpace {
+    pub architectures: Vec<String>,
+    pub size_batches: Values<usize>,
+    pub num_epochs: Values<usize>,
+    pub learning_rates: Values<f64>,
+}
+
+pub enum Values<T> {
+    List(Vec<T>),
+    Range(T, T),
+}
+
+impl<T: Copy + std::str::FromStr + PartialOrd> Values<T> {
+    fn parse(text: &str, key: &str) -> Result<Values<T>, String> {
+        let invalid = || format!("{}の値が不正です。", key);
+        if let Some((low, high)) = text.split_once("..") {
+            let low = low.trim().parse::<T>().map_err(|_| invalid())?;
+            let high = high.trim().parse::<T>().map_err(|_| invalid())?;
+            if low > high {
+                return Err(invalid());
+            }
+            return Ok(Values::Range(low, high));
+        }
+        text.split('|')
+            .map(|value| value.trim().parse::<T>().map_err(|_| invalid()))
+            .collect::<Result<Vec<T>, String>>()
+            .map(Values::List)
+    }
+
+    fn list(&self) -> Option<&[T]> {
+        match self {
+            Values::List(values) => Some(values),
+            Values::Range(..) => None,
+        }
+    }
+}
+
+impl SearchSpace {
+    pub fn parse(text: &str) -> Result<SearchSpace, String> {
+        let mut architectures = None;
+        let mut size_batches = None;
+        let mut num_epochs = None;
+        let mut learning_rates = None;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, values) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}には=がありません。", line))?;
+            let (key, values) = (key.trim(), values.trim());
+            match key {
+                "architecture" => {
+                    let specs: Vec<String> =
+                        values.split('|').map(|s| s.trim().to_string()).collect();
+                    for spec in &specs {
+                        Architecture::parse(spec, IMAGE_SHAPE)?;
+                    }
+                    architectures = Some(specs);
+                }
+                "size_batch" => size_batches = Some(Values::parse(values, key)?),
+                "num_epoch" => num_epochs = Some(Values::parse(values, key)?),
+                "learning_rate" => learning_rates = Some(Values::parse(values, key)?),
+                _ => return Err(format!("{}は知らないハイパーパラメータです。", key)),
+            }
+        }
+        let missing = |key: &str| format!("{}がありません。", key);
+        let space = SearchSpace {
+            architectures: architectures.ok_or_else(|| missing("architecture"))?,
+            size_batches: size_batches.ok_or_else(|| missing("size_batch"))?,
+            num_epochs: num_epochs.ok_or_else(|| missing("num_epoch"))?,
+            learning_rates: learning_rates.ok_or_else(|| missing("learning_rate"))?,
+        };
+        let is_positive = |values: &Values<usize>| match values {
+            Values::List(values) => values.iter().all(|&x| x > 0),
+            Values::Range(low, _) => *low > 0,
+        };
+        if !is_positive(&space.size_batches) || !is_positive(&space.num_epochs) {
+            return Err("バッチサイズとエポック数は1以上にして下さい。".to_string());
+        }
+        //範囲の学習率は対数を取って選ぶので、0以下や無限大は使えない。候補も同じにする
+        let is_valid_learning_rate = |x: f64| x.is_finite() && x > 0.0;
+        let are_valid_learning_rates = match &space.learning_rates {
+            Values::List(values) => values.iter().all(|&x| is_valid_learning_rate(x)),
+            Values::Range(low, high) => {
+                is_valid_learning_rate(*low) && is_valid_learning_rate(*high)
+            }
+        };
+        if !are_valid_learning_rates {
+            return Err("学習率は0より大きい有限の値にして下さい。".to_string());
+        }
+        Ok(space)
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> Candidate {
+        let sample_usize = |values: &Values<usize>, rng: &mut R| match values {
+            Values::List(values) => *values.choose(rng).unwrap(),
+            Values::Range(low, high) => rng.gen_range(*low..=*high),
+        };
+        Candidate {
+            architecture: self.architectures.choose(rng).unwrap().clone(),
+            size_batch: sample_usize(&self.size_batches, rng),
+            num_epoch: sample_usize(&self.num_epochs, rng),
+            learning_rate: match &self.learning_rates {
+                Values::List(values) => *values.choose(rng).unwrap(),
+                Values::Range(low, high) if low < high => rng.gen_range(low.ln()..high.ln()).exp(),
+                Values::Range(low, _) => *low,
+            },
+        }
+    }
+
+    //num_epochの最大値
+    fn max_num_epoch(&self) -> usize {
+        match &self.num_epochs {
+            Values::List(values) => *values.iter().max().unwrap(),
+            Values::Range(_, high) => *high,
+        }
+    }
+
+    //範囲を含まず、グリッドサーチができるか
+    pub fn is_enumerable(&self) -> bool {
+        self.grid().is_some()
+    }
+
+    //全ての組み合わせ。範囲を含む時はNone
+    fn grid(&self) -> Option<Vec<Candidate>> {
+        let mut candidates = Vec::new();
+        for architecture in &self.architectures {
+            for &size_batch in self.size_batches.list()? {
+                for &num_epoch in self.num_epochs.list()? {
+                    for &learning_rate in self.learning_rates.list()? {
+                        candidates.push(Candidate {
+                            architecture: architecture.clone(),
+                            size_batch,
+                            num_epoch,
+                            learning_rate,
+                        });
+                    }
+                }
+            }
+        }
+        Some(candidates)
+    }
+}
+
+pub enum Strategy {
+    Grid,
+    Random {
+        num_trials: usize,
+    },
+    //無作為に選んだ候補を少ないエポック数で訓練し、上位1/reductionだけをreduction倍のエポック数で訓練し直す事を繰り返す
+    //最後に残った候補を探索空間のnum_epochの最大値で訓練するように、各段のエポック数を決める
+    SuccessiveHalving {
+        num_candidates: usize,
+        reduction: usize,
+    },
+}
+
+#[derive(Clone)]
+pub struct Candidate {
+    pub architecture: String,
+    pub size_batch: usize,
+    pub num_epoch: usize,
+    pub learning_rate: f64,
+}
+
+pub struct Trial {
+    pub candidate: Candidate,
+    pub validation_loss: f64,
+    pub validation_accuracy: f64,
+    pub elapsed_seconds: f64,
+}
+
+pub struct SearchResult {
+    //成績の良い順
+    pub leaderboard: Vec<Trial>,
+    pub best_layers: Vec<Layer>,
+    pub best_history: TrainingHistory,
+}
+
+//検証用データでの正答率が高い方、同じなら損失が小さい方を前にする
+fn compare_trials(a: &Trial, b: &Trial) -> Ordering {
+    b.validation_accuracy
+        .total_cmp(&a.validation_accuracy)
+        .then(a.validation_loss.total_cmp(&b.validation_loss))
+}
+
+//訓練に使うデータと検証に使うデータ
+pub struct SearchData<'a> {
+    pub train: &'a dyn Dataset,
+    pub validation: &'a dyn Dataset,
+    //trainから求めた入力の正規化
+    pub normalization: &'a InputNormalization,
+}
+
+struct Searcher<'a> {
+    data: &'a SearchData<'a>,
+    loss: &'a Loss,
+    regularization: &'a Regularization,
+    augmentation: &'a Augmentation,
+    trials: Vec<Trial>,
+    best: Option<(Vec<Layer>, TrainingHistory)>,
+}
+
+impl Searcher<'_> {
+    fn run(&mut self, candidate: &Candidate, trial_i: usize, num_trials: usize) -> io::Result<()> {
+        let mut layers = Architecture::parse(&candidate.architecture, IMAGE_SHAPE)
+            .unwrap()
+            .build();
+        let settings = TrainingSettings {
+            size_batch: candidate.size_batch,
+            num_epoch: candidate.num_epoch,
+            learning_rate: candidate.learning_rate,
+            loss: self.loss.clone(),
+            regularization: self.regularization.clone(),
+            display: ProgressDisplay::Quiet,
+            augmentation: self.augmentation.clone(),
+            normalization: self.data.normalization.clone(),
+        };
+        let start = Instant::now();
+        let history = training::train(
+            &mut layers,
+            self.data.train,
+            self.data.validation,
+            &settings,
+        )?;
+        let last_epoch = history.epochs.last().unwrap();
+        let trial = Trial {
+            candidate: candidate.clone(),
+            validation_loss: last_epoch.validation_loss.unwrap_or(f64::NAN),
+            validation_accuracy: last_epoch.validation_accuracy.unwrap_or(0.0),
+            elapsed_seconds: start.elapsed().as_secs_f64(),
+        };
+        println!(
+            "trial: {}/{}\t{}\tbatch: {}\tepoch: {}\tlearning rate: {:.6}\tvalidation cost: {:.4}\tvalidation accuracy: {:.1}%\t{:.1}s",
+            trial_i + 1,
+            num_trials,
+            candidate.architecture,
+            candidate.size_batch,
+            candidate.num_epoch,
+            candidate.learning_rate,
+            trial.validation_loss,
+            100.0 * trial.validation_accuracy,
+            trial.elapsed_seconds
+        );
+        let is_best = self
+            .trials
+            .iter()
+            .all(|other| compare_trials(&trial, other) == Ordering::Less);
+        if is_best {
+            self.best = Some((layers, history));
+        }
+        self.trials.push(trial);
+        Ok(())
+    }
+}
+
+//各候補を訓練して検証用データで比べる
+pub fn search(
+    space: &SearchSpace,
+    strategy: &Strategy,
+    data: &SearchData,
+    loss: &Loss,
+    regularization: &Regularization,
+    augmentation: &Augmentation,
+) -> io::Result<Result<SearchResult, String>> {
+    let mut searcher = Searcher {
+        data,
+        loss,
+        regularization,
+        augmentation,
+        trials: Vec::new(),
+        best: None,
+    };
+    let mut rng = rand::thread_rng();
+    match strategy {
+        Strategy::Grid => {
+            let Some(candidates) = space.grid() else {
+                return Ok(Err(
+                    "グリッドサーチでは範囲ではなく候補を並べて下さい。".to_string()
+                ));
+            };
+            for (i, candidate) in candidates.iter().enumerate() {
+                searcher.run(candidate, i, candidates.len())?;
+            }
+        }
+        Strategy::Random { num_trials } => {
+            for i in 0..*num_trials {
+                searcher.run(&space.sample(&mut rng), i, *num_trials)?;
+            }
+        }
+        Strategy::SuccessiveHalving {
+            num_candidates,
+            reduction,
+        } => {
+            let mut candidates: Vec<Candidate> = (0..*num_candidates)
+                .map(|_| space.sample(&mut rng))
+                .collect();
+            //候補が1つになるまでの段の数
+            let mut num_rungs = 1;
+            let mut num_remaining = *num_candidates;
+            while num_remaining > 1 {
+                num_remaining = num_remaining.div_ceil(*reduction);
+                num_rungs += 1;
+            }
+            let max_num_epoch = space.max_num_epoch();
+            for rung_i in 0..num_rungs {
+                let scale = reduction.saturating_pow((num_rungs - 1 - rung_i) as u32);
+                let num_epoch = (max_num_epoch / scale).max(1);
+                println!(
+                    "{}個の候補を{}エポックずつ訓練します。",
+                    candidates.len(),
+                    num_epoch
+                );
+                let first_i = searcher.trials.len();
+                let num_trials = candidates.len();
+                for (i, candidate) in candidates.iter_mut().enumerate() {
+                    candidate.num_epoch = num_epoch;
+                    searcher.run(candidate, i, num_trials)?;
+                }
+                if candidates.len() <= 1 {
+                    break;
+                }
+                let mut rung: Vec<&Trial> = searcher.trials[first_i..].iter().collect();
+                rung.sort_by(|a, b| compare_trials(a, b));
+                let num_survivors = candidates.len().div_ceil(*reduction);
+                candidates = rung[..num_survivors]
+                    .iter()
+                    .map(|trial| trial.candidate.clone())
+                    .collect();
+            }
+        }
+    }
+
+    let Some((best_layers, best_history)) = searcher.best else {
+        return Ok(Err("候補が1つもありません。".to_string()));
+    };
+    let mut leaderboard = searcher.trials;
+    leaderboard.sort_by(compare_trials);
+    Ok(Ok(SearchResult {
+        leaderboard,
+        best_layers,
+        best_history,
+    }))
+}
+
+impl SearchResult {
+    pub fn print_leaderboard(&self, num_rows: usize) {
+        println!("順位\t検証用の正答率\t検証用の損失\tバッチ\tエポック\t学習率\t構造");
+        for (rank, trial) in self.leaderboard.iter().take(num_rows).enumerate() {
+            println!(
+                "{}\t{:.1}%\t{:.4}\t{}\t{}\t{:.6}\t{}",
+                rank + 1,
+                100.0 * trial.validation_accuracy,
+                trial.validation_loss,
+                trial.candidate.size_batch,
+                trial.candidate.num_epoch,
+                trial.candidate.learning_rate,
+                trial.candidate.architecture
+            );
+        }
+    }
+
+    pub fn save_leaderboard(&self, path: &Path) -> io::Result<()> {
+        let mut csv = String::from(
+            "rank,validation_accuracy,validation_loss,size_batch,num_epoch,learning_rate,elapsed_seconds,architecture\n",
+        );
+        for (rank, trial) in self.leaderboard.iter().enumerate() {
+            //構造の文字列はカンマを含むので引用符で囲む
+            csv += &format!(
+                "{},{},{},{},{},{},{},\"{}\"\n",
+                rank + 1,
+                trial.validation_accuracy,
+                trial.validation_loss,
+                trial.candidate.size_batch,
+                trial.candidate.num_epoch,
+                trial.candidate.learning_rate,
+                trial.elapsed_seconds,
+                trial.candidate.architecture
+            );
+        }
+        fs::write(path, csv)
+    }
+}
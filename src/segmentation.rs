@@ -0,0 +1,329 @@
+use crate::image::GrayImage;
+use crate::light_network::{self, LightLayer};
+use crate::preprocessing::{self, Preprocessing};
+
+//一番大きい連結成分に対してこれより小さい面積の成分は汚れとみなして捨てる
+const MIN_AREA_RATE: f64 = 0.05;
+//横に並んだ2つの成分の重なりが狭い方の幅のこの割合以上なら同じ数字とみなす(5の上の棒など)
+const MERGE_OVERLAP_RATE: f64 = 0.5;
+//独立した数字が見つからない時に使う、高さに対する1文字の幅の目安
+const DEFAULT_ASPECT: f64 = 0.65;
+//射影の谷がこれより浅ければ(列の平均との比)谷が無いとみなし、等間隔で切る
+const VALLEY_RATE: f64 = 0.5;
+
+//画像から切り出した1つの数字の範囲(元の画像の座標、両端を含む)
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub left: usize,
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+}
+
+impl Segment {
+    pub fn width(&self) -> usize {
+        self.right - self.left + 1
+    }
+
+    pub fn height(&self) -> usize {
+        self.bottom - self.top + 1
+    }
+}
+
+pub struct RecognizedDigit {
+    pub segment: Segment,
+    //モデルに入力した28x28の画像
+    pub input: GrayImage,
+    pub label: usize,
+    pub confidence: f64,
+}
+
+pub struct RecognizedNumber {
+    pub digits: Vec<RecognizedDigit>,
+}
+
+impl RecognizedNumber {
+    pub fn text(&self) -> String {
+        self.digits
+            .iter()
+            .map(|digit| char::from_digit(digit.label as u32, 10).unwrap())
+            .collect()
+    }
+
+    //各桁の確率の積
+    pub fn confidence(&self) -> f64 {
+        self.digits.iter().map(|digit| digit.confidence).product()
+    }
+}
+
+//白黒を揃えて(文字が明るい)大津の方法で2値化し、文字の画素をtrueにする
+pub fn binarize(image: &GrayImage) -> (GrayImage, Vec<bool>) {
+    let image = preprocessing::auto_invert(image);
+    let mut histogram = [0usize; 256];
+    for &value in &image.pixels {
+        histogram[(value.clamp(0.0, 1.0) * 255.0).round() as usize] += 1;
+    }
+    let total = image.pixels.len() as f64;
+    let total_sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| i as f64 * count as f64)
+        .sum();
+    //クラス間分散が最大になる閾値を選ぶ
+    let (mut best_threshold, mut best_variance) = (0, -1f64);
+    let (mut background_count, mut background_sum) = (0f64, 0f64);
+    for (threshold, &count) in histogram.iter().enumerate() {
+        background_count += count as f64;
+        background_sum += threshold as f64 * count as f64;
+        let foreground_count = total - background_count;
+        if background_count == 0.0 || foreground_count == 0.0 {
+            continue;
+        }
+        let background_mean = background_sum / background_count;
+        let foreground_mean = (total_sum - background_sum) / foreground_count;
+        let variance =
+            background_count * foreground_count * (background_mean - foreground_mean).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_threshold = threshold;
+        }
+    }
+    let threshold = best_threshold as f64 / 255.0;
+    let mask = image
+        .pixels
+        .iter()
+        .map(|&value| value > threshold)
+        .collect();
+    (image, mask)
+}
+
+struct Component {
+    segment: Segment,
+    area: usize,
+}
+
+//8近傍で繋がった文字の画素に番号を付ける。背景はNone
+fn label_components(
+    mask: &[bool],
+    width: usize,
+    height: usize,
+) -> (Vec<Option<usize>>, Vec<Component>) {
+    let mut labels: Vec<Option<usize>> = vec![None; mask.len()];
+    let mut components = Vec::new();
+    for start in 0..mask.len() {
+        if !mask[start] || labels[start].is_some() {
+            continue;
+        }
+        let id = components.len();
+        let (x, y) = (start % width, start / width);
+        let mut component = Component {
+            segment: Segment {
+                left: x,
+                top: y,
+                right: x,
+                bottom: y,
+            },
+            area: 0,
+        };
+        labels[start] = Some(id);
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            component.area += 1;
+            let segment = &mut component.segment;
+            segment.left = segment.left.min(x);
+            segment.right = segment.right.max(x);
+            segment.top = segment.top.min(y);
+            segment.bottom = segment.bottom.max(y);
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let ni = ny * width + nx;
+                    if mask[ni] && labels[ni].is_none() {
+                        labels[ni] = Some(id);
+                        stack.push(ni);
+                    }
+                }
+            }
+        }
+        components.push(component);
+    }
+    (labels, components)
+}
+
+//数字1つ分の成分の集まりと、その中で使う列の範囲
+struct Group {
+    members: Vec<usize>,
+    segment: Segment,
+}
+
+//連結成分を数字毎にまとめ、左から順に並べる。繋がった数字は縦の射影の谷で切り分ける
+pub fn segment_digits(image: &GrayImage) -> Vec<(Segment, GrayImage)> {
+    let (ink, mask) = binarize(image);
+    let (labels, components) = label_components(&mask, ink.width, ink.height);
+    let max_area = components.iter().map(|c| c.area).max().unwrap_or(0);
+    let mut groups: Vec<Group> = components
+        .iter()
+        .enumerate()
+        .filter(|(_, component)| component.area as f64 >= max_area as f64 * MIN_AREA_RATE)
+        .map(|(id, component)| Group {
+            members: vec![id],
+            segment: component.segment,
+        })
+        .collect();
+    groups.sort_by_key(|group| group.segment.left);
+    groups = merge_overlapping(groups);
+
+    //独立していそうな数字(幅が高さ以下)の幅の中央値を1文字の幅とする
+    let mut heights: Vec<usize> = groups.iter().map(|g| g.segment.height()).collect();
+    heights.sort();
+    let median_height = heights.get(heights.len() / 2).copied().unwrap_or(0) as f64;
+    let mut widths: Vec<usize> = groups
+        .iter()
+        .filter(|g| g.segment.width() <= g.segment.height())
+        .map(|g| g.segment.width())
+        .collect();
+    widths.sort();
+    let digit_width = widths
+        .get(widths.len() / 2)
+        .map(|&width| width as f64)
+        .unwrap_or(median_height * DEFAULT_ASPECT)
+        .max(1.0);
+
+    let mut digits = Vec::new();
+    for group in groups {
+        let num_digits = ((group.segment.width() as f64 / digit_width).round() as usize).max(1);
+        let mut cuts = vec![group.segment.left];
+        if group.segment.width() > group.segment.height() && num_digits >= 2 {
+            cuts.extend(find_cuts(&group, &labels, ink.width, num_digits));
+        }
+        cuts.push(group.segment.right + 1);
+        for range in cuts.windows(2) {
+            if let Some(digit) = crop_digit(&group, &labels, &ink, range[0], range[1]) {
+                digits.push(digit);
+            }
+        }
+    }
+    digits
+}
+
+//横方向に大きく重なる成分を1つにまとめる(groupsは左端の順)
+fn merge_overlapping(groups: Vec<Group>) -> Vec<Group> {
+    let mut merged: Vec<Group> = Vec::new();
+    for group in groups {
+        if let Some(last) = merged.last_mut() {
+            let overlap = last.segment.right.min(group.segment.right) as isize
+                - last.segment.left.max(group.segment.left) as isize
+                + 1;
+            let narrower = last.segment.width().min(group.segment.width());
+            if overlap as f64 >= narrower as f64 * MERGE_OVERLAP_RATE {
+                last.members.extend(group.members);
+                let segment = &mut last.segment;
+                segment.left = segment.left.min(group.segment.left);
+                segment.right = segment.right.max(group.segment.right);
+                segment.top = segment.top.min(group.segment.top);
+                segment.bottom = segment.bottom.max(group.segment.bottom);
+                continue;
+            }
+        }
+        merged.push(group);
+    }
+    merged
+}
+
+//等間隔に切った位置の前後で、縦に射影した文字の画素数が一番少ない列を探す
+fn find_cuts(
+    group: &Group,
+    labels: &[Option<usize>],
+    width: usize,
+    num_digits: usize,
+) -> Vec<usize> {
+    let segment = group.segment;
+    let profile: Vec<usize> = (segment.left..=segment.right)
+        .map(|x| {
+            (segment.top..=segment.bottom)
+                .filter(|&y| labels[y * width + x].is_some_and(|id| group.members.contains(&id)))
+                .count()
+        })
+        .collect();
+    let mean = profile.iter().sum::<usize>() as f64 / profile.len() as f64;
+    let step = segment.width() as f64 / num_digits as f64;
+    let window = (step / 4.0).max(1.0) as usize;
+    (1..num_digits)
+        .map(|k| {
+            let expected = (k as f64 * step).round() as usize;
+            let from = expected.saturating_sub(window).max(1);
+            let to = (expected + window).min(profile.len() - 1);
+            let valley = (from..=to)
+                .min_by_key(|&x| (profile[x], (x as isize - expected as isize).unsigned_abs()));
+            match valley {
+                Some(x) if (profile[x] as f64) < mean * VALLEY_RATE => segment.left + x,
+                _ => segment.left + expected,
+            }
+        })
+        .collect()
+}
+
+//グループに属する画素のうち[from, to)の列にあるものだけを、元の明るさで切り出す
+fn crop_digit(
+    group: &Group,
+    labels: &[Option<usize>],
+    ink: &GrayImage,
+    from: usize,
+    to: usize,
+) -> Option<(Segment, GrayImage)> {
+    let belongs = |x: usize, y: usize| {
+        labels[y * ink.width + x].is_some_and(|id| group.members.contains(&id))
+    };
+    let rows: Vec<usize> = (group.segment.top..=group.segment.bottom)
+        .filter(|&y| (from..to).any(|x| belongs(x, y)))
+        .collect();
+    let segment = Segment {
+        left: from,
+        top: *rows.first()?,
+        right: to - 1,
+        bottom: *rows.last()?,
+    };
+    let mut crop = GrayImage::new(segment.width(), segment.height());
+    for y in segment.top..=segment.bottom {
+        for x in segment.left..=segment.right {
+            if belongs(x, y) {
+                crop.set(x - segment.left, y - segment.top, ink.get(x, y));
+            }
+        }
+    }
+    Some((segment, crop))
+}
+
+//複数桁の数字が書かれた画像を1桁ずつ切り出し、左から順に判定する
+//切り出した画像には必ず切り出しと中心合わせを掛け、白黒は2値化の時に揃えるので反転はしない
+pub fn recognize_number(
+    layers: &mut [LightLayer],
+    image: &GrayImage,
+    preprocessing: &Preprocessing,
+) -> RecognizedNumber {
+    let preprocessing = Preprocessing {
+        auto_invert: false,
+        center: true,
+        ..*preprocessing
+    };
+    let digits = segment_digits(image)
+        .into_iter()
+        .map(|(segment, crop)| {
+            let input = preprocessing.apply_to_image(&crop);
+            light_network::guess_answer(layers, &input.pixels);
+            let probabilities = layers.last().unwrap().get_activations();
+            let (label, &confidence) = probabilities
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap();
+            RecognizedDigit {
+                segment,
+                input,
+                label,
+                confidence,
+            }
+        })
+        .collect();
+    RecognizedNumber { digits }
+}
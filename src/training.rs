@@ -0,0 +1,152 @@
+use crate::augmentation::Augmentation;
+use crate::dashboard::{Dashboard, ProgressDisplay};
+use crate::dataset::Dataset;
+use crate::history::{IterationRecord, TrainingHistory};
+use crate::input_normalization::InputNormalization;
+use crate::loss::Loss;
+use crate::network::{self, Layer};
+use crate::regularization::Regularization;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{self, Error, ErrorKind};
+use std::time::Instant;
+
+pub struct TrainingSettings {
+    pub size_batch: usize,
+    pub num_epoch: usize,
+    pub learning_rate: f64,
+    pub loss: Loss,
+    pub regularization: Regularization,
+    pub display: ProgressDisplay,
+    pub augmentation: Augmentation,
+    //訓練用データから求めた統計量。データ拡張の後に掛ける
+    pub normalization: InputNormalization,
+}
+
+//trainのデータからミニバッチを無作為に選んで訓練し、各エポックの最後にvalidationのデータで検証する
+pub fn train(
+    layers: &mut [Layer],
+    train: &dyn Dataset,
+    validation: &dyn Dataset,
+    settings: &TrainingSettings,
+) -> io::Result<TrainingHistory> {
+    if train.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "訓練用のデータが1枚もありません。",
+        ));
+    }
+    if settings.size_batch == 0 || settings.num_epoch == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "バッチサイズとエポック数は1以上にして下さい。",
+        ));
+    }
+    let size_batch = settings.size_batch;
+    let num_epoch = settings.num_epoch;
+    let mut learning_rate = settings.learning_rate;
+    let num_iteration: usize = (train.len() / size_batch).max(1);
+    //学習の進み具合に伴って学習率を小さくしていく為の係数
+    let learning_rate_coefficient = 100f64.powf(1.0 / (num_iteration * num_epoch) as f64);
+    let mut history = TrainingHistory::default();
+    let start = Instant::now();
+    let mut dashboard = Dashboard::new(settings.display, num_epoch, num_iteration, size_batch);
+    //種が指定されていれば、ミニバッチの選び方もデータ拡張も毎回同じになる
+    let mut rng = match settings.augmentation.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    for epoch in 0..num_epoch {
+        for iteration in 0..num_iteration {
+            let mut batch_images: Vec<Vec<f64>> = Vec::with_capacity(size_batch);
+            let mut batch_labels: Vec<usize> = Vec::with_capacity(size_batch);
+            for _batch in 0..size_batch {
+                let data_index = rng.gen_range(0..train.len());
+                let (image, label) = train.get(data_index)?;
+                let image = if settings.augmentation.is_empty() {
+                    image
+                } else {
+                    settings.augmentation.apply(&image, &mut rng)
+                };
+                batch_images.push(settings.normalization.apply(&image));
+                batch_labels.push(label as usize);
+            }
+            let mut cost =
+                network::backpropagation(layers, &batch_images, &batch_labels, &settings.loss);
+            let outputs = layers.last().unwrap().get_activations();
+            let num_correct = outputs
+                .iter()
+                .zip(&batch_labels)
+                .filter(|(probabilities, &label)| predicted_label(probabilities) == label)
+                .count();
+            let gradient_norm = network::gradient_norm(layers, size_batch);
+            network::apply_neurons_fixes(
+                layers,
+                size_batch,
+                learning_rate,
+                &settings.regularization,
+            );
+            cost += network::regularization_penalty(layers, &settings.regularization);
+            history.iterations.push(IterationRecord {
+                epoch,
+                iteration,
+                loss: cost,
+                accuracy: num_correct as f64 / size_batch as f64,
+                learning_rate,
+                gradient_norm,
+                elapsed_seconds: start.elapsed().as_secs_f64(),
+            });
+            dashboard.update_iteration(history.iterations.last().unwrap());
+            learning_rate /= learning_rate_coefficient;
+        }
+        let validation = if validation.is_empty() {
+            None
+        } else {
+            Some(evaluate(
+                layers,
+                validation,
+                &settings.normalization,
+                &settings.loss,
+            )?)
+        };
+        let record = history.finish_epoch(epoch, validation, start.elapsed().as_secs_f64());
+        dashboard.finish_epoch(record);
+    }
+    Ok(history)
+}
+
+//判定時と同じ順伝播で損失の平均と正答率を返す
+pub fn evaluate(
+    layers: &mut [Layer],
+    dataset: &dyn Dataset,
+    normalization: &InputNormalization,
+    loss: &Loss,
+) -> io::Result<(f64, f64)> {
+    let mut cost = 0f64;
+    let mut num_correct = 0;
+    for index in 0..dataset.len() {
+        let (image, label) = dataset.get(index)?;
+        network::guess_answer(layers, &normalization.apply(&image));
+        let probabilities = &layers.last().unwrap().get_activations()[0];
+        let label = label as usize;
+        cost += loss.value(probabilities, label);
+        if predicted_label(probabilities) == label {
+            num_correct += 1;
+        }
+    }
+    let count = dataset.len() as f64;
+    Ok((cost / count, num_correct as f64 / count))
+}
+
+fn predicted_label(probabilities: &[f64]) -> usize {
+    probabilities.iter().enumerate().fold(
+        0,
+        |max_i, (i, &x)| {
+            if x > probabilities[max_i] {
+                i
+            } else {
+                max_i
+            }
+        },
+    )
+}
@@ -0,0 +1,32 @@
+use crate::dataset::{Dataset, ImageFolderDataset};
+use crate::image::GrayImage;
+use crate::image_file;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//手動試験で正しいラベルを付けた画像を貯めておくディレクトリ。ImageFolderDatasetと同じくラベル毎のサブフォルダに分ける
+pub const USER_DATASET_DIR: &str = "user_datas";
+
+//モデルに入力した画像(前処理済み)をlabelのフォルダにname.pngとして保存する。同じ名前があれば番号を付ける
+pub fn save_sample(image: &[f64], label: u8, name: &str) -> io::Result<PathBuf> {
+    let label_path = Path::new(USER_DATASET_DIR).join(label.to_string());
+    fs::create_dir_all(&label_path)?;
+    let mut path = label_path.join(format!("{}.png", name));
+    let mut number = 1;
+    while path.exists() {
+        path = label_path.join(format!("{}_{}.png", name, number));
+        number += 1;
+    }
+    image_file::save_image(&path, &GrayImage::from_pixels(28, 28, image.to_vec()))?;
+    Ok(path)
+}
+
+//画像は保存時に前処理済みなので、ここでは前処理をしない。1枚も無ければNone
+pub fn open() -> io::Result<Option<ImageFolderDataset>> {
+    if !Path::new(USER_DATASET_DIR).is_dir() {
+        return Ok(None);
+    }
+    let dataset = ImageFolderDataset::open(Path::new(USER_DATASET_DIR))?;
+    Ok((!dataset.is_empty()).then_some(dataset))
+}
@@ -0,0 +1,54 @@
+use number_recognizer::architecture::Architecture;
+use number_recognizer::convolution::IMAGE_SHAPE;
+
+//文字列にして読み直すと同じ構造になる事を確かめる。architecture.txtはこの文字列で保存する
+fn round_trip(spec: &str) {
+    let architecture = Architecture::parse(spec, IMAGE_SHAPE).unwrap();
+    let written = architecture.to_string();
+    let reparsed = Architecture::parse(&written, IMAGE_SHAPE)
+        .unwrap_or_else(|message| panic!("{} -> {}: {}", spec, written, message));
+    assert!(
+        architecture.layers == reparsed.layers,
+        "{} -> {} で構造が変わりました",
+        spec,
+        written
+    );
+}
+
+#[test]
+fn dense_activations() {
+    for activation in ["leaky_relu", "relu", "sigmoid", "tanh", "linear"] {
+        round_trip(&format!(
+            "dense(32,{0})-dense(16,{0})-dense(10)",
+            activation
+        ));
+    }
+}
+
+#[test]
+fn hidden_linear_layer_is_kept() {
+    let architecture = Architecture::parse("dense(32,linear)-dense(10)", IMAGE_SHAPE).unwrap();
+    assert_eq!(architecture.to_string(), "dense(32,linear)-dense(10)");
+}
+
+#[test]
+fn normalizations_and_dropout() {
+    for normalization in ["", "-batchnorm", "-layernorm"] {
+        for dropout in ["", "-dropout(0.25)"] {
+            round_trip(&format!(
+                "dense(32,relu){0}{1}-dense(16,linear){0}{1}-dense(10)",
+                normalization, dropout
+            ));
+        }
+    }
+}
+
+#[test]
+fn convolutions_and_pooling() {
+    for activation in ["leaky_relu", "relu", "sigmoid", "tanh", "linear"] {
+        round_trip(&format!(
+            "conv(4,3,{0})-maxpool(2)-conv(8,3,2,1,{0})-avgpool(2,1)-flatten-dense(10)",
+            activation
+        ));
+    }
+}
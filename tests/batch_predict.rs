@@ -0,0 +1,18 @@
+use glob::Pattern;
+use number_recognizer::batch_predict;
+use std::fs;
+
+#[cfg(unix)]
+#[test]
+fn symlink_cycle_is_not_followed() {
+    let dir = std::env::temp_dir().join(format!("find_files_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    fs::write(dir.join("a/1.png"), []).unwrap();
+    fs::write(dir.join("a/b/2.png"), []).unwrap();
+    //a/b/loopはaを指すので、辿ると終わらない
+    std::os::unix::fs::symlink(dir.join("a"), dir.join("a/b/loop")).unwrap();
+    let files = batch_predict::find_files(&dir, &Pattern::new("*.png").unwrap()).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files, vec![dir.join("a/1.png"), dir.join("a/b/2.png")]);
+}
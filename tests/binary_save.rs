@@ -0,0 +1,16 @@
+use number_recognizer::architecture::Architecture;
+use number_recognizer::binary_save;
+use number_recognizer::convolution::IMAGE_SHAPE;
+
+#[test]
+fn dropout_is_not_saved() {
+    let spec =
+        "conv(4,3)-flatten-dense(32,relu)-batchnorm-dropout(0.3)-dense(16)-dropout(0.5)-dense(10)";
+    let layers = Architecture::parse(spec, IMAGE_SHAPE).unwrap().build();
+    let saved = binary_save::saved_architecture(&layers).to_string();
+    assert!(!saved.contains("dropout"));
+    assert_eq!(
+        saved,
+        "conv(4,3)-flatten-dense(32,relu)-batchnorm-dense(16)-dense(10)"
+    );
+}
@@ -0,0 +1,132 @@
+use number_recognizer::architecture::Architecture;
+use number_recognizer::convolution::Shape;
+use number_recognizer::gradient_check::{self, GradientCheck};
+use number_recognizer::loss::Loss;
+use number_recognizer::network::{self, Layer};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const INPUT_SHAPE: Shape = Shape {
+    channels: 1,
+    height: 6,
+    width: 6,
+};
+const EPSILON: f64 = 1e-5;
+const TOLERANCE: f64 = 1e-4;
+
+//重みも入力も固定したシードから作るので、毎回同じ結果になる
+//ReLUや最大プーリングの折れ目をまたぐパラメータはcheck_gradientsが片側差分と比べるので、シードには頼らない
+fn check(spec: &str, loss: &Loss) -> GradientCheck {
+    let architecture = Architecture::parse(spec, INPUT_SHAPE).unwrap();
+    let mut rng = StdRng::seed_from_u64(32);
+    let mut layers = architecture.build_with_rng(&mut rng);
+    let images: Vec<Vec<f64>> = (0..4)
+        .map(|_| (0..INPUT_SHAPE.size()).map(|_| rng.gen::<f64>()).collect())
+        .collect();
+    let answers: Vec<usize> = (0..4).map(|_| rng.gen_range(0..10)).collect();
+    let result = gradient_check::check_gradients(&mut layers, &images, &answers, loss, EPSILON);
+    assert_eq!(result.gradients.len(), architecture.num_parameters());
+    let (worst_i, error) = result.worst().unwrap();
+    assert!(
+        error < TOLERANCE,
+        "{} ({}): parameter {} analytical {} numerical {} (one-sided {:?})",
+        spec,
+        loss.name(),
+        worst_i,
+        result.gradients[worst_i].0,
+        result.gradients[worst_i].1,
+        result.one_sided[worst_i]
+    );
+    result
+}
+
+fn cross_entropy() -> Loss {
+    Loss::CrossEntropy {
+        label_smoothing: 0.0,
+    }
+}
+
+#[test]
+fn dense_activations() {
+    for activation in ["leaky_relu", "relu", "sigmoid", "tanh", "linear"] {
+        check(
+            &format!("dense(8,{0})-dense(6,{0})-dense(10)", activation),
+            &cross_entropy(),
+        );
+    }
+}
+
+#[test]
+fn losses() {
+    let losses = [
+        cross_entropy(),
+        Loss::CrossEntropy {
+            label_smoothing: 0.1,
+        },
+        Loss::WeightedCrossEntropy {
+            class_weights: (0..10).map(|i| 0.5 + i as f64 * 0.1).collect(),
+        },
+        Loss::Focal { gamma: 2.0 },
+        Loss::MeanSquaredError,
+    ];
+    for loss in &losses {
+        check("dense(8,tanh)-dense(10)", loss);
+    }
+}
+
+#[test]
+fn normalizations() {
+    check("dense(8,tanh)-batchnorm-dense(10)", &cross_entropy());
+    check("dense(8,sigmoid)-layernorm-dense(10)", &cross_entropy());
+}
+
+#[test]
+fn running_statistics_are_restored() {
+    let architecture = Architecture::parse("dense(8,relu)-batchnorm-dense(10)", INPUT_SHAPE);
+    let mut layers = architecture
+        .unwrap()
+        .build_with_rng(&mut StdRng::seed_from_u64(32));
+    let statistics = |layers: &mut [Layer]| {
+        let mut statistics = Vec::new();
+        network::for_each_running_statistic(layers, |mean, variance| {
+            statistics.push((*mean, *variance))
+        });
+        statistics
+    };
+    let before = statistics(&mut layers);
+    let images = vec![vec![0.3; INPUT_SHAPE.size()], vec![0.8; INPUT_SHAPE.size()]];
+    gradient_check::check_gradients(&mut layers, &images, &[1, 4], &cross_entropy(), EPSILON);
+    assert_eq!(statistics(&mut layers), before);
+}
+
+#[test]
+fn dropout_is_disabled_and_restored() {
+    let architecture = Architecture::parse("dense(8,tanh)-dropout(0.5)-dense(10)", INPUT_SHAPE);
+    let mut layers = architecture
+        .unwrap()
+        .build_with_rng(&mut StdRng::seed_from_u64(32));
+    let images = vec![vec![0.5; INPUT_SHAPE.size()]; 2];
+    let result =
+        gradient_check::check_gradients(&mut layers, &images, &[3, 7], &cross_entropy(), EPSILON);
+    assert!(result.max_relative_error() < TOLERANCE);
+    let Layer::Dense(layer) = &layers[0] else {
+        panic!();
+    };
+    assert_eq!(layer.dropout_rate, 0.5);
+}
+
+#[test]
+fn convolutions_and_pooling() {
+    check(
+        "conv(3,3,1,1,tanh)-maxpool(2)-conv(2,2,2,0,sigmoid)-flatten-dense(10)",
+        &cross_entropy(),
+    );
+    check(
+        "conv(2,3)-avgpool(2)-flatten-dense(5,tanh)-dense(10)",
+        &Loss::MeanSquaredError,
+    );
+    check(
+        "conv(2,3,2,1,relu)-maxpool(2,1)-dense(10)",
+        &Loss::Focal { gamma: 1.0 },
+    );
+}
@@ -0,0 +1,61 @@
+use number_recognizer::image_file::{self, ImageFormat};
+use std::io::ErrorKind;
+
+//幅、高さ、ビット数と画素のデータからBITMAPINFOHEADERのBMPを作る
+fn bmp(width: i32, height: i32, bits_per_pixel: u16, data: &[u8]) -> Vec<u8> {
+    let mut bytes = b"BM".to_vec();
+    bytes.extend((54 + data.len() as u32).to_le_bytes());
+    bytes.extend([0; 4]);
+    bytes.extend(54u32.to_le_bytes());
+    bytes.extend(40u32.to_le_bytes());
+    bytes.extend(width.to_le_bytes());
+    bytes.extend(height.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(bits_per_pixel.to_le_bytes());
+    bytes.extend([0; 24]);
+    bytes.extend(data);
+    bytes
+}
+
+fn assert_invalid(bytes: &[u8], format: ImageFormat) {
+    let result = image_file::decode_image(bytes, format);
+    assert!(result.is_err_and(|error| error.kind() == ErrorKind::InvalidData));
+}
+
+#[test]
+fn bmp_is_decoded() {
+    //下の行から並ぶので、白い画素は上の行の左
+    let data = [0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 0, 0, 0, 0, 0];
+    let image = image_file::decode_image(&bmp(2, 2, 24, &data), ImageFormat::Bmp).unwrap();
+    assert_eq!(image.pixels, vec![1.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn bmp_with_huge_or_truncated_data_is_rejected() {
+    assert_invalid(b"BM", ImageFormat::Bmp);
+    assert_invalid(&bmp(i32::MAX, i32::MAX, 32, &[]), ImageFormat::Bmp);
+    assert_invalid(&bmp(100000, -100000, 24, &[0; 16]), ImageFormat::Bmp);
+    assert_invalid(&bmp(2, 2, 24, &[0; 15]), ImageFormat::Bmp);
+    assert_invalid(&bmp(2, 2, 16, &[0; 16]), ImageFormat::Bmp);
+}
+
+#[test]
+fn pnm_is_decoded() {
+    let image = image_file::decode_image(b"P2\n2 1\n# comment\n4\n0 4\n", ImageFormat::Pnm);
+    assert_eq!(image.unwrap().pixels, vec![0.0, 1.0]);
+    let image = image_file::decode_image(b"P5 1 2 255\n\x00\xff", ImageFormat::Pnm);
+    assert_eq!(image.unwrap().pixels, vec![0.0, 1.0]);
+}
+
+#[test]
+fn pnm_with_huge_or_truncated_data_is_rejected() {
+    assert_invalid(b"", ImageFormat::Pnm);
+    assert_invalid(b"P", ImageFormat::Pnm);
+    assert_invalid(b"P5", ImageFormat::Pnm);
+    assert_invalid(b"P5\n2", ImageFormat::Pnm);
+    let huge = format!("P6\n{0} {0}\n255\n", usize::MAX / 2);
+    assert_invalid(huge.as_bytes(), ImageFormat::Pnm);
+    assert_invalid(b"P5\n100000 100000\n255\n\x00\x00", ImageFormat::Pnm);
+    assert_invalid(b"P5\n2 2\n255\n\x00\x00\x00", ImageFormat::Pnm);
+    assert_invalid(b"P2\n3 3\n255\n0 0 0", ImageFormat::Pnm);
+}
@@ -0,0 +1,41 @@
+use number_recognizer::image::GrayImage;
+use number_recognizer::preprocessing::Preprocessing;
+
+//(6,4)から(14,23)までの右下がりの線
+fn slanted_line() -> GrayImage {
+    let mut image = GrayImage::new(28, 28);
+    for y in 4..24 {
+        let x = 6.0 + (y - 4) as f64 * 8.0 / 19.0;
+        image.set(x.round() as usize, y, 1.0);
+    }
+    image
+}
+
+//各行の明るさの重心のx座標
+fn row_centers(image: &GrayImage) -> Vec<f64> {
+    (0..image.height)
+        .filter_map(|y| {
+            let row: Vec<f64> = (0..image.width).map(|x| image.get(x, y)).collect();
+            let sum: f64 = row.iter().sum();
+            (sum > 0.0).then(|| {
+                row.iter()
+                    .enumerate()
+                    .map(|(x, v)| x as f64 * v)
+                    .sum::<f64>()
+                    / sum
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn deskew_keeps_width_without_cropping() {
+    let image = slanted_line();
+    let center_x = image.center_of_mass().unwrap().0;
+    let deskewed = Preprocessing::parse("d").unwrap().apply_to_image(&image);
+    assert_eq!((deskewed.width, deskewed.height), (28, 28));
+    //傾きが打ち消されて縦になり、横に潰されないので重心の列に並ぶ
+    for x in row_centers(&deskewed) {
+        assert!((x - center_x).abs() < 1.0, "{} {}", x, center_x);
+    }
+}
@@ -0,0 +1,21 @@
+use number_recognizer::search::{SearchSpace, Values};
+
+fn parse(learning_rate: &str) -> Result<SearchSpace, String> {
+    SearchSpace::parse(&format!(
+        "architecture = dense(10)\nsize_batch = 16\nnum_epoch = 1\nlearning_rate = {}",
+        learning_rate
+    ))
+}
+
+#[test]
+fn learning_rate_range() {
+    let space = parse("0.001..0.1").unwrap();
+    assert!(
+        matches!(space.learning_rates, Values::Range(low, high) if low == 0.001 && high == 0.1)
+    );
+    assert!(parse("0..0.1").is_err());
+    assert!(parse("-0.1..0.1").is_err());
+    assert!(parse("0.001..inf").is_err());
+    assert!(parse("0 | 0.1").is_err());
+    assert!(parse("0.01 | 0.1").is_ok());
+}